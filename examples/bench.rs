@@ -0,0 +1,61 @@
+use std::io::sink;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use brainfuck::interpreter::{BuildOptions, Interpreter, OptLevel};
+
+/// Times runs of a program that reads no input, such as
+/// `brainfuck/mandelbrot.bf`, discarding its output. Every run is printed,
+/// since on a busy machine they can vary more than two builds differ. To
+/// compare two builds, alternate a few runs of each rather than timing one
+/// after the other.
+#[derive(Parser)]
+struct Cli {
+    path: std::path::PathBuf,
+    /// Number of timed runs
+    #[arg(long, default_value_t = 5)]
+    runs: u32,
+}
+
+fn main() {
+    let args = Cli::parse();
+    let code = match std::fs::read(&args.path) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("could not read {}: {}", args.path.display(), err);
+            exit(1);
+        }
+    };
+    let options = BuildOptions {
+        opt: OptLevel::O2,
+        ..Default::default()
+    };
+    let interpreter = match Interpreter::build_bytes_with(&code, &options) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+
+    let mut times = vec![];
+    for _ in 0..args.runs.max(1) {
+        let start = Instant::now();
+        if let Err(err) = interpreter.execute(&mut std::io::empty(), &mut sink()) {
+            eprintln!("{}", err);
+            exit(1);
+        }
+        let time = start.elapsed();
+        println!("run {}: {:?}", times.len() + 1, time);
+        times.push(time);
+    }
+    let total: Duration = times.iter().sum();
+    println!(
+        "runs: {}, min: {:?}, mean: {:?}",
+        times.len(),
+        times.iter().min().unwrap(),
+        total / times.len() as u32
+    );
+}
@@ -0,0 +1,79 @@
+use std::io::{stdin, Write};
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+use clap::Parser;
+
+use brainfuck::input::LineInput;
+use brainfuck::interpreter::{Interpreter, VmState};
+
+/// Steps through a program, redrawing the cells around the pointer after
+/// every op.
+#[derive(Parser)]
+struct Cli {
+    path: std::path::PathBuf,
+    /// Delay between ops in milliseconds
+    #[arg(long, default_value_t = 50)]
+    delay: u64,
+    /// Number of cells to draw
+    #[arg(long, default_value_t = 16)]
+    width: usize,
+}
+
+fn render(state: &VmState, width: usize, output: &[u8]) {
+    let start = state.ptr().saturating_sub(width / 2);
+    let end = (start + width).min(state.data().len());
+
+    let mut screen = String::from("\x1b[2J\x1b[H");
+    screen += &format!("ip = {}, ptr = {}\n\n", state.ip(), state.ptr());
+    for idx in start..end {
+        screen += &format!("{:>5}", idx);
+    }
+    screen += "\n";
+    for idx in start..end {
+        screen += &format!("{:>5}", state.data()[idx]);
+    }
+    screen += "\n";
+    screen += &" ".repeat((state.ptr() - start) * 5);
+    screen += "    ^\n\n";
+    screen += &String::from_utf8_lossy(output);
+    print!("{}", screen);
+    std::io::stdout().flush().unwrap();
+}
+
+fn main() {
+    let args = Cli::parse();
+    let code = match std::fs::read(&args.path) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("could not read {}: {}", args.path.display(), err);
+            exit(1);
+        }
+    };
+    let interpreter = match Interpreter::build_bytes(&code) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+
+    let mut state = VmState::new();
+    let mut input = LineInput::new(stdin().lock());
+    let mut output = vec![];
+    loop {
+        render(&state, args.width, &output);
+        match interpreter.step(&mut state, &mut input, &mut output) {
+            Ok(true) => sleep(Duration::from_millis(args.delay)),
+            Ok(false) => {
+                println!("\n-- finished --");
+                break;
+            }
+            Err(err) => {
+                println!("\n-- {} --", err);
+                exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::interpreter::Op::{
+    self, Add, AddAt, Clear, ClearRange, Dump, Halt, In, JmpNz, JmpZ, Move, MoveAdd, MulAdd, Out,
+    OutRange, PrintPtr, Scan, SwapAcc,
+};
+use crate::optimizer;
+
+/// A line of assembly that is not a valid op, or a loop left unclosed.
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    line: usize,
+}
+
+impl AsmError {
+    /// The line of the error, counted from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid asm at line {}", self.line)
+    }
+}
+
+impl Error for AsmError {}
+
+/// One op per line, indented by loop depth. Each loop gets a label which
+/// its `jz` and `jnz` both name.
+pub(crate) fn format(ops: &[Op]) -> String {
+    let mut result = String::new();
+    let mut labels = vec![];
+    let mut next_label = 1;
+    for op in ops {
+        let line = match op {
+            JmpZ { .. } => {
+                labels.push(next_label);
+                next_label += 1;
+                format!("jz L{}", next_label - 1)
+            }
+            JmpNz { .. } => format!("jnz L{}", labels.pop().expect("unbalanced jumps")),
+            _ => op.to_string(),
+        };
+        let depth = labels.len() - matches!(op, JmpZ { .. }) as usize;
+        result += &"    ".repeat(depth);
+        result += &line;
+        result.push('\n');
+    }
+    result
+}
+
+/// Reads the ops written by `format`. Blank lines and anything after a `;`
+/// are ignored, and labels can be any word as long as each `jnz` names the
+/// innermost open `jz`.
+pub(crate) fn parse(text: &str) -> Result<Vec<Op>, AsmError> {
+    let mut ops = vec![];
+    // Labels of the open loops with the lines of their `jz`.
+    let mut labels: Vec<(&str, usize)> = vec![];
+    for (idx, line) in text.lines().enumerate() {
+        let err = || AsmError { line: idx + 1 };
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args: Vec<&str> = match args.trim() {
+            "" => vec![],
+            args => args.split(',').map(str::trim).collect(),
+        };
+        let num = |i: usize| args[i].parse::<isize>().map_err(|_| err());
+        let op = match (name, args.len()) {
+            ("move", 1) => Move { d: num(0)? },
+            ("add", 1) => Add { d: num(0)? },
+            ("out", 0) => Out,
+            ("in", 0) => In,
+            ("halt", 0) => Halt,
+            ("dump", 0) => Dump,
+            ("swap_acc", 0) => SwapAcc,
+            ("print_ptr", 0) => PrintPtr,
+            ("clear", 0) => Clear,
+            ("clear", 1) => {
+                let (start, end) = args[0].split_once("..").ok_or_else(err)?;
+                let start: isize = start.parse().map_err(|_| err())?;
+                let end: isize = end.parse().map_err(|_| err())?;
+                if end < start {
+                    return Err(err());
+                }
+                ClearRange {
+                    start_off: start,
+                    len: (end - start) as usize,
+                }
+            }
+            ("add_at", 2) => AddAt {
+                off: num(0)?,
+                d: num(1)?,
+            },
+            ("scan", 1) => Scan { d: num(0)? },
+            ("move_add", 1) => MoveAdd { off: num(0)? },
+            ("mul_add", 2) => MulAdd {
+                off: num(0)?,
+                factor: num(1)?,
+            },
+            ("out_range", 3) => {
+                let byte = |i: usize| args[i].parse::<i8>().map_err(|_| err());
+                OutRange {
+                    off: num(0)?,
+                    pre: byte(1)?,
+                    step: byte(2)?,
+                }
+            }
+            ("jz", 1) => {
+                labels.push((args[0], idx + 1));
+                JmpZ { addr: 0 }
+            }
+            ("jnz", 1) => match labels.pop() {
+                Some((label, _)) if label == args[0] => JmpNz { addr: 0 },
+                _ => return Err(err()),
+            },
+            _ => return Err(err()),
+        };
+        ops.push(op);
+    }
+    if let Some((_, line)) = labels.pop() {
+        return Err(AsmError { line });
+    }
+    optimizer::link(&mut ops);
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "
+            in          ; the counter
+            jz loop
+                add_at 2, 3
+                clear -1..2
+                jz inner
+                    scan -1
+                jnz inner
+                add -1
+            jnz loop
+            mul_add -1, 4
+            move_add 1
+            clear
+            swap_acc
+            print_ptr
+            halt
+            dump
+            out
+        ";
+        let ops = parse(text).unwrap();
+        assert_eq!(17, ops.len());
+        assert_eq!(JmpZ { addr: 9 }, ops[1]);
+        assert_eq!(
+            ClearRange {
+                start_off: -1,
+                len: 3
+            },
+            ops[3]
+        );
+        assert_eq!(JmpNz { addr: 5 }, ops[6]);
+        assert_eq!(MulAdd { off: -1, factor: 4 }, ops[9]);
+        assert_eq!(parse(&format(&ops)).unwrap(), ops);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, line) in [
+            ("add", 1),
+            ("add 1, 2", 1),
+            ("move x", 1),
+            ("out 1", 1),
+            ("clear 2..1", 1),
+            ("jz a\n\njnz b", 3),
+            ("jnz a", 1),
+            ("add 1\njz a\nadd 1", 2),
+            ("jump 3", 1),
+        ] {
+            assert_eq!(Err(AsmError { line }), parse(text), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::interpreter::Op::{
+    self, Add, AddAt, Clear, ClearRange, Dump, Halt, In, JmpNz, JmpZ, Move, MoveAdd, MulAdd, Out,
+    OutRange, PrintPtr, Scan, SwapAcc,
+};
+use crate::optimizer;
+
+/// Bytes that do not decode to a valid program.
+#[derive(Debug, PartialEq)]
+pub struct BytecodeError {
+    offset: usize,
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid bytecode at byte {}", self.offset)
+    }
+}
+
+impl Error for BytecodeError {}
+
+/// Writes `n` as a zigzag LEB128 varint, so small negative numbers stay short.
+fn push_varint(result: &mut Vec<u8>, n: isize) {
+    let mut n = (((n as i64) << 1) ^ ((n as i64) >> 63)) as u64;
+    while n >= 0x80 {
+        result.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    result.push(n as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<isize, BytecodeError> {
+    let start = *pos;
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or(BytecodeError { offset: *pos })?;
+        *pos += 1;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((n >> 1) as i64 ^ -((n & 1) as i64)) as isize);
+        }
+    }
+    Err(BytecodeError { offset: start })
+}
+
+/// Tag of the metadata section, which follows the ops.
+const METADATA: u8 = 0xff;
+/// Layout of the metadata section, for readers to reject ones they do not
+/// know.
+const METADATA_VERSION: isize = 1;
+
+fn push_string(result: &mut Vec<u8>, s: &str) {
+    push_varint(result, s.len() as isize);
+    result.extend_from_slice(s.as_bytes());
+}
+
+fn read_string(bytes: &[u8], pos: &mut usize) -> Result<String, BytecodeError> {
+    let start = *pos;
+    let len = read_varint(bytes, pos)?;
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| pos.checked_add(len))
+        .filter(|&end| end <= bytes.len())
+        .ok_or(BytecodeError { offset: start })?;
+    let s = String::from_utf8(bytes[*pos..end].to_vec())
+        .map_err(|_| BytecodeError { offset: start })?;
+    *pos = end;
+    Ok(s)
+}
+
+/// Appends the metadata section: its tag, version and number of entries,
+/// then every key and value as a length and UTF-8 bytes.
+pub(crate) fn encode_metadata(result: &mut Vec<u8>, metadata: &BTreeMap<String, String>) {
+    result.push(METADATA);
+    push_varint(result, METADATA_VERSION);
+    push_varint(result, metadata.len() as isize);
+    for (key, value) in metadata {
+        push_string(result, key);
+        push_string(result, value);
+    }
+}
+
+fn decode_metadata(
+    bytes: &[u8],
+    pos: &mut usize,
+) -> Result<BTreeMap<String, String>, BytecodeError> {
+    let offset = *pos;
+    if read_varint(bytes, pos)? != METADATA_VERSION {
+        return Err(BytecodeError { offset });
+    }
+    let offset = *pos;
+    let len = read_varint(bytes, pos)?;
+    if len < 0 {
+        return Err(BytecodeError { offset });
+    }
+    let mut metadata = BTreeMap::new();
+    for _ in 0..len {
+        let key = read_string(bytes, pos)?;
+        metadata.insert(key, read_string(bytes, pos)?);
+    }
+    Ok(metadata)
+}
+
+/// One tag byte per op followed by its operands. Jump addresses are left out
+/// and recomputed when decoding.
+pub(crate) fn encode(ops: &[Op]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(ops.len() * 2);
+    for op in ops {
+        let (tag, a, b) = op.parts();
+        result.push(tag);
+        match op {
+            Move { .. } | Add { .. } | Scan { .. } | MoveAdd { .. } => push_varint(&mut result, a),
+            ClearRange { .. } | AddAt { .. } | MulAdd { .. } => {
+                push_varint(&mut result, a);
+                push_varint(&mut result, b);
+            }
+            OutRange { pre, step, .. } => {
+                push_varint(&mut result, a);
+                push_varint(&mut result, *pre as isize);
+                push_varint(&mut result, *step as isize);
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Decodes the ops and, when present, the metadata section after them.
+pub(crate) fn decode(bytes: &[u8]) -> Result<(Vec<Op>, BTreeMap<String, String>), BytecodeError> {
+    let mut ops = vec![];
+    let mut metadata = BTreeMap::new();
+    let mut depth = 0usize;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let offset = pos;
+        pos += 1;
+        let op = match bytes[offset] {
+            0 => Move {
+                d: read_varint(bytes, &mut pos)?,
+            },
+            1 => Add {
+                d: read_varint(bytes, &mut pos)?,
+            },
+            2 => Out,
+            3 => In,
+            4 => {
+                depth += 1;
+                JmpZ { addr: 0 }
+            }
+            5 if depth > 0 => {
+                depth -= 1;
+                JmpNz { addr: 0 }
+            }
+            6 => Halt,
+            7 => Dump,
+            15 => PrintPtr,
+            8 => Clear,
+            9 => {
+                let start_off = read_varint(bytes, &mut pos)?;
+                let len = read_varint(bytes, &mut pos)?;
+                if len < 0 {
+                    return Err(BytecodeError { offset });
+                }
+                ClearRange {
+                    start_off,
+                    len: len as usize,
+                }
+            }
+            10 => AddAt {
+                off: read_varint(bytes, &mut pos)?,
+                d: read_varint(bytes, &mut pos)?,
+            },
+            11 => Scan {
+                d: read_varint(bytes, &mut pos)?,
+            },
+            12 => MoveAdd {
+                off: read_varint(bytes, &mut pos)?,
+            },
+            13 => SwapAcc,
+            14 => MulAdd {
+                off: read_varint(bytes, &mut pos)?,
+                factor: read_varint(bytes, &mut pos)?,
+            },
+            16 => {
+                let off = read_varint(bytes, &mut pos)?;
+                let mut byte = || {
+                    i8::try_from(read_varint(bytes, &mut pos)?)
+                        .map_err(|_| BytecodeError { offset })
+                };
+                let (pre, step) = (byte()?, byte()?);
+                OutRange { off, pre, step }
+            }
+            METADATA => {
+                metadata = decode_metadata(bytes, &mut pos)?;
+                if pos < bytes.len() {
+                    return Err(BytecodeError { offset: pos });
+                }
+                break;
+            }
+            _ => return Err(BytecodeError { offset }),
+        };
+        ops.push(op);
+    }
+    if depth > 0 {
+        return Err(BytecodeError {
+            offset: bytes.len(),
+        });
+    }
+    optimizer::link(&mut ops);
+    Ok((ops, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for n in [0, 1, -1, 63, -64, 64, 300, -300, isize::MAX, isize::MIN] {
+            let mut bytes = vec![];
+            push_varint(&mut bytes, n);
+            let mut pos = 0;
+            assert_eq!(n, read_varint(&bytes, &mut pos).unwrap());
+            assert_eq!(bytes.len(), pos);
+        }
+        let mut bytes = vec![];
+        push_varint(&mut bytes, -1);
+        assert_eq!(vec![1], bytes);
+        assert_eq!(
+            Err(BytecodeError { offset: 0 }),
+            read_varint(&[0xff; 11], &mut 0)
+        );
+    }
+
+    #[test]
+    fn test_metadata() {
+        let metadata = BTreeMap::from([
+            ("ops".to_string(), "1".to_string()),
+            ("name".to_string(), "héllo".to_string()),
+        ]);
+        let mut bytes = encode(&[Out]);
+        encode_metadata(&mut bytes, &metadata);
+        assert_eq!((vec![Out], metadata), decode(&bytes).unwrap());
+
+        // Old bytecode has no section.
+        assert!(decode(&encode(&[Out])).unwrap().1.is_empty());
+
+        let mut bytes = encode(&[Out]);
+        encode_metadata(&mut bytes, &BTreeMap::new());
+        // Versions are zigzag varints, so 4 is version 2.
+        bytes[2] = 4;
+        assert_eq!(Err(BytecodeError { offset: 2 }), decode(&bytes));
+        bytes[2] = 2;
+        bytes.push(2);
+        assert_eq!(Err(BytecodeError { offset: 4 }), decode(&bytes));
+        assert_eq!(
+            Err(BytecodeError { offset: 3 }),
+            decode(&[METADATA, 2, 2, 20, b'a'])
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::interpreter::Op::{
+    self, Add, AddAt, Clear, ClearRange, Dump, Halt, In, JmpNz, JmpZ, Move, MoveAdd, MulAdd, Out,
+    OutRange, PrintPtr, Scan, SwapAcc,
+};
+use crate::interpreter::TAPE_SIZE;
+
+/// What happens when the pointer leaves the tape.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PointerMode {
+    /// Stop with a `DataOverflow`.
+    #[default]
+    Error,
+    /// Continue from the other end of the tape. Build with
+    /// `BuildOptions::pointer_mode` set to this, since `OptLevel::O2`
+    /// otherwise takes cells at different offsets to be different cells.
+    Wrap,
+    /// Stop at the first or last cell. This applies to compiled moves, so a
+    /// run like `<<>` moves by its net distance before clamping. Ops made by
+    /// `OptLevel::O2` that reach a cell off the tape without moving there
+    /// stop with a `DataOverflow`; build with `BuildOptions::pointer_mode`
+    /// set to this to leave them out.
+    Clamp,
+    /// Extend the tape when the pointer moves past its end, up to
+    /// `Config::max_tape` cells. Moving left of cell 0 is still an error.
+    Grow,
+}
+
+/// What happens when an add takes a cell outside its `CellMode`'s range,
+/// `0..=255` by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CellOverflow {
+    #[default]
+    Wrap,
+    /// Stop with a `CellOverflow` error. Runs of `+-` are checked once
+    /// folded, so `-+` on a zero cell is not an error. Loops that
+    /// `OptLevel::O2` replaces, like `[+]` or `[->+<]`, are only checked for
+    /// their end result, so `[+]` clears its cell; build with
+    /// `BuildOptions::checked_cells` to check every step.
+    Error,
+}
+
+/// What a cell holds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CellMode {
+    /// A byte, with adds outside `0..=255` handled by `CellOverflow`.
+    #[default]
+    U8,
+    /// A boolean: an add leaves the cell at 1 if the result is positive and
+    /// at 0 otherwise, so `.` writes 0 or 1 bytes. Like
+    /// `CellOverflow::Error`, this applies to folded runs of `+-` and needs
+    /// `BuildOptions::checked_cells` for the loops `OptLevel::O2` replaces.
+    Bit,
+    /// A two's-complement `i8`, with adds outside `-128..=127` handled by
+    /// `CellOverflow`. `.` writes the cell's byte, so -1 prints as 255, and
+    /// loops test for zero as usual.
+    Signed,
+}
+
+/// What `,` does once the input is exhausted, or when a non-blocking reader
+/// has nothing available and fails with `WouldBlock`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EofMode {
+    /// Stop with an io error.
+    #[default]
+    Error,
+    /// Leave the cell as it was.
+    Unchanged,
+    /// Set the cell to 0.
+    Zero,
+}
+
+/// How `.` writes the current cell.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The cell's byte as is.
+    #[default]
+    Raw,
+    /// The value in decimal followed by a space, like `200 `.
+    Decimal,
+}
+
+/// Options for `Interpreter::execute_with_config`, set either directly or
+/// through the chainable setters:
+///
+/// ```
+/// # use brainfuck::config::{Config, EofMode};
+/// let config = Config::new().tape_size(1000).eof(EofMode::Zero).max_steps(10_000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of cells; moving the pointer outside `0..tape_size` is
+    /// handled according to `pointer_mode`. At least one cell is always
+    /// allocated.
+    pub tape_size: usize,
+    /// Attach the cells around the pointer to runtime errors.
+    pub snapshot_on_error: bool,
+    pub pointer_mode: PointerMode,
+    /// Most cells a growing tape may have; reaching past it is a
+    /// `DataOverflow`.
+    pub max_tape: usize,
+    pub eof: EofMode,
+    pub cell_overflow: CellOverflow,
+    pub cell_mode: CellMode,
+    pub output_format: OutputFormat,
+    /// Times a write of `.`'s output, or of an `output_chunk`, is tried
+    /// again when the writer accepts none of it, before failing with an io
+    /// error.
+    pub write_retries: u32,
+    /// Values of the first cells when the program starts; the rest are 0.
+    pub initial_cells: Vec<u8>,
+    /// Stop with a `StepLimit` error instead of executing more ops.
+    pub max_steps: Option<u64>,
+    /// Stop without an error once `.` has run this many times. Each `.`
+    /// outputs one byte, or a whole number with `OutputFormat::Decimal`.
+    pub output_preview: Option<usize>,
+    /// Bytes of output collected before writing them out at once. The
+    /// output is also written before every input read and at the end.
+    pub output_chunk: usize,
+    /// Bytes of input read at once and kept for the following `,`, with
+    /// the output written before each such read. Input read past the
+    /// program's last `,` is lost.
+    pub input_chunk: usize,
+    /// Byte sequences replaced in the input before `,` reads it, such as
+    /// `\r\n` with `\n`. None by default.
+    pub input_translation: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tape_size(mut self, tape_size: usize) -> Self {
+        self.tape_size = tape_size;
+        self
+    }
+
+    pub fn snapshot_on_error(mut self, snapshot_on_error: bool) -> Self {
+        self.snapshot_on_error = snapshot_on_error;
+        self
+    }
+
+    pub fn pointer_mode(mut self, pointer_mode: PointerMode) -> Self {
+        self.pointer_mode = pointer_mode;
+        self
+    }
+
+    pub fn max_tape(mut self, max_tape: usize) -> Self {
+        self.max_tape = max_tape;
+        self
+    }
+
+    pub fn eof(mut self, eof: EofMode) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    pub fn cell_overflow(mut self, cell_overflow: CellOverflow) -> Self {
+        self.cell_overflow = cell_overflow;
+        self
+    }
+
+    pub fn cell_mode(mut self, cell_mode: CellMode) -> Self {
+        self.cell_mode = cell_mode;
+        self
+    }
+
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    pub fn write_retries(mut self, write_retries: u32) -> Self {
+        self.write_retries = write_retries;
+        self
+    }
+
+    pub fn initial_cells(mut self, initial_cells: Vec<u8>) -> Self {
+        self.initial_cells = initial_cells;
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn output_preview(mut self, output_preview: usize) -> Self {
+        self.output_preview = Some(output_preview);
+        self
+    }
+
+    pub fn output_chunk(mut self, output_chunk: usize) -> Self {
+        self.output_chunk = output_chunk;
+        self
+    }
+
+    pub fn input_chunk(mut self, input_chunk: usize) -> Self {
+        self.input_chunk = input_chunk;
+        self
+    }
+
+    pub fn translate_input(mut self, from: &[u8], to: &[u8]) -> Self {
+        self.input_translation.push((from.to_vec(), to.to_vec()));
+        self
+    }
+
+    /// Reads Windows line endings as `\n`.
+    pub fn crlf_input(self) -> Self {
+        self.translate_input(b"\r\n", b"\n")
+    }
+
+    /// The behavior of most classic interpreters: 30000 u8 cells, a pointer
+    /// that wraps around the tape, and `,` leaving the cell unchanged at EOF.
+    pub fn classic() -> Self {
+        Self {
+            tape_size: 30000,
+            pointer_mode: PointerMode::Wrap,
+            eof: EofMode::Unchanged,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tape_size: TAPE_SIZE,
+            snapshot_on_error: false,
+            pointer_mode: PointerMode::default(),
+            max_tape: usize::MAX,
+            eof: EofMode::default(),
+            cell_overflow: CellOverflow::default(),
+            cell_mode: CellMode::default(),
+            output_format: OutputFormat::default(),
+            write_retries: 0,
+            initial_cells: vec![],
+            max_steps: None,
+            output_preview: None,
+            output_chunk: 1,
+            input_chunk: 1,
+            input_translation: vec![],
+        }
+    }
+}
+
+/// The gas each kind of op costs in `Interpreter::execute_gas`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasSchedule {
+    /// Moves, including scans for a zero cell.
+    pub moves: u64,
+    /// Adds, clears and other changes to cells.
+    pub arithmetic: u64,
+    pub jumps: u64,
+    pub input: u64,
+    pub output: u64,
+    /// Halts, dumps and pointer prints.
+    pub other: u64,
+}
+
+impl GasSchedule {
+    pub(crate) fn cost(&self, op: Op) -> u64 {
+        match op {
+            Move { .. } | Scan { .. } => self.moves,
+            Add { .. }
+            | AddAt { .. }
+            | Clear
+            | ClearRange { .. }
+            | MoveAdd { .. }
+            | MulAdd { .. }
+            | SwapAcc => self.arithmetic,
+            JmpZ { .. } | JmpNz { .. } => self.jumps,
+            In => self.input,
+            Out | OutRange { .. } => self.output,
+            Halt | Dump | PrintPtr => self.other,
+        }
+    }
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self {
+            moves: 1,
+            arithmetic: 1,
+            jumps: 1,
+            input: 1,
+            output: 1,
+            other: 1,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::interpreter::{BuildError, RuntimeError};
+
+/// Any error produced while loading, building or running a program.
+#[derive(Debug)]
+pub enum BfError {
+    Build(BuildError),
+    Runtime(RuntimeError),
+    Io(std::io::Error),
+}
+
+impl Display for BfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BfError::Build(err) => write!(f, "build err: {}", err),
+            BfError::Runtime(err) => write!(f, "runtime err: {}", err),
+            BfError::Io(err) => write!(f, "io err: {}", err),
+        }
+    }
+}
+
+impl Error for BfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BfError::Build(err) => Some(err),
+            BfError::Runtime(err) => Some(err),
+            BfError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<BuildError> for BfError {
+    fn from(err: BuildError) -> Self {
+        BfError::Build(err)
+    }
+}
+
+impl From<RuntimeError> for BfError {
+    fn from(err: RuntimeError) -> Self {
+        BfError::Runtime(err)
+    }
+}
+
+impl From<std::io::Error> for BfError {
+    fn from(err: std::io::Error) -> Self {
+        BfError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_from() {
+        let err: BfError = Interpreter::build("]").unwrap_err().into();
+        assert!(matches!(err, BfError::Build(_)));
+        assert_eq!(
+            "build err: bracket not match, line = 1, col = 1",
+            err.to_string()
+        );
+
+        let inter = Interpreter::build("<").unwrap();
+        let err: BfError = inter
+            .execute(&mut std::io::empty(), &mut std::io::sink())
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, BfError::Runtime(_)));
+        assert_eq!("runtime err: data overflow, idx = -1", err.to_string());
+
+        let err: BfError = std::io::Error::other("disk").into();
+        assert!(matches!(err, BfError::Io(_)));
+        assert_eq!("io err: disk", err.to_string());
+        assert!(err.source().is_some());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Read};
+
+/// Reads input a whole line at a time and serves it to successive `,` reads,
+/// reading the next line once the current one is used up.
+pub struct LineInput<R> {
+    inner: R,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> LineInput<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for LineInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            self.inner.read_until(b'\n', &mut self.line)?;
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Replaces byte sequences in the input, such as `\r\n` with `\n`. Where
+/// several sequences match, the longest one wins.
+pub struct TranslateInput<R> {
+    inner: R,
+    rules: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Bytes read which may start a sequence.
+    pending: Vec<u8>,
+    translated: VecDeque<u8>,
+}
+
+impl<R: Read> TranslateInput<R> {
+    pub fn new(inner: R, rules: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            inner,
+            rules,
+            pending: vec![],
+            translated: VecDeque::new(),
+        }
+    }
+
+    /// Reads while the pending bytes could still grow into a longer
+    /// sequence, then settles the first of them.
+    fn fill(&mut self) -> std::io::Result<()> {
+        while self.pending.is_empty()
+            || self
+                .rules
+                .iter()
+                .any(|(from, _)| from.len() > self.pending.len() && from.starts_with(&self.pending))
+        {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                break;
+            }
+            self.pending.push(byte[0]);
+        }
+        let rule = self
+            .rules
+            .iter()
+            .filter(|(from, _)| !from.is_empty() && self.pending.starts_with(from))
+            .rev()
+            .max_by_key(|(from, _)| from.len());
+        match rule {
+            Some((from, to)) => {
+                self.translated.extend(to);
+                self.pending.drain(..from.len());
+            }
+            None if !self.pending.is_empty() => {
+                self.translated.push_back(self.pending.remove(0));
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for TranslateInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.translated.is_empty() {
+            self.fill()?;
+            if self.translated.is_empty() && self.pending.is_empty() {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.translated.len());
+        for (dst, src) in buf.iter_mut().zip(self.translated.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+/// Endless pseudo-random input from a seed, the same on every machine, for
+/// running a program on inputs it does not expect. `Config::max_steps`
+/// bounds programs that read until some byte.
+pub struct RandomInput {
+    state: u64,
+    /// The bytes of the last generated value, of which `used` are read.
+    bytes: [u8; 8],
+    used: usize,
+}
+
+impl RandomInput {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            bytes: [0; 8],
+            used: 8,
+        }
+    }
+
+    /// The next value of a SplitMix64 generator.
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Read for RandomInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        for byte in buf.iter_mut() {
+            if self.used == self.bytes.len() {
+                self.bytes = self.next().to_le_bytes();
+                self.used = 0;
+            }
+            *byte = self.bytes[self.used];
+            self.used += 1;
+        }
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn test_line_input() {
+        let mut input = LineInput::new(Cursor::new(b"hi\nyo\n".to_vec()));
+        let mut c = [0u8; 1];
+        for expected in b"hi\nyo\n" {
+            input.read_exact(&mut c).unwrap();
+            assert_eq!(*expected, c[0]);
+        }
+        assert_eq!(0, input.read(&mut c).unwrap());
+
+        let inter = Interpreter::build(",>,>,>,<<<.>.>>.").unwrap();
+        let mut input = LineInput::new(Cursor::new(b"ab\ncd\n".to_vec()));
+        let mut out = vec![];
+        inter.execute(&mut input, &mut out).unwrap();
+        assert_eq!(b"abc".to_vec(), out);
+    }
+
+    #[test]
+    fn test_translate_input() {
+        let translate = |input: &[u8], rules: &[(&[u8], &[u8])]| {
+            let rules = rules
+                .iter()
+                .map(|&(a, b)| (a.to_vec(), b.to_vec()))
+                .collect();
+            let mut out = vec![];
+            TranslateInput::new(input, rules)
+                .read_to_end(&mut out)
+                .unwrap();
+            out
+        };
+        let crlf: &[(&[u8], &[u8])] = &[(b"\r\n", b"\n")];
+        assert_eq!(b"a\nb\r\n\r".to_vec(), translate(b"a\r\nb\r\r\n\r", crlf));
+        assert_eq!(b"a\r\n".to_vec(), translate(b"a\r\n", &[]));
+        assert_eq!(
+            b"xy-b".to_vec(),
+            translate(b"aab", &[(b"aa", b"xy-"), (b"a", b"z")])
+        );
+        assert_eq!(
+            b"zb".to_vec(),
+            translate(b"ab", &[(b"aa", b""), (b"a", b"z")])
+        );
+    }
+
+    #[test]
+    fn test_random_input() {
+        let inter = Interpreter::build(",.,.,.,.,.,.,.,.,.,.").unwrap();
+        let echo = |seed| {
+            let mut out = vec![];
+            inter
+                .execute(&mut RandomInput::new(seed), &mut out)
+                .unwrap();
+            out
+        };
+        assert_eq!(10, echo(7).len());
+        assert_eq!(echo(7), echo(7));
+        assert_ne!(echo(7), echo(8));
+
+        let mut bytes = [0; 10];
+        RandomInput::new(7).read_exact(&mut bytes).unwrap();
+        assert_eq!(echo(7), bytes);
+    }
+}
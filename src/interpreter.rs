@@ -1,17 +1,116 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::io::{Read, Write};
+use std::io::{sink, stderr, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::interpreter::Op::{Add, In, JmpNz, JmpZ, Move, Out};
+use crate::asm::{self, AsmError};
+use crate::bytecode::{self, BytecodeError};
+use crate::config::{
+    CellMode, CellOverflow, Config, EofMode, GasSchedule, OutputFormat, PointerMode,
+};
+use crate::input::TranslateInput;
+use crate::interpreter::Op::{
+    Add, AddAt, Clear, ClearRange, Dump, Halt, In, JmpNz, JmpZ, Move, MoveAdd, MulAdd, Out,
+    OutRange, PrintPtr, Scan, SwapAcc,
+};
+use crate::optimizer;
 
-#[derive(Debug, PartialEq)]
-enum Op {
+pub(crate) const TAPE_SIZE: usize = 30000;
+
+/// The bytes of the eight commands; everything else is a comment unless
+/// `BuildOptions` gives it a meaning.
+pub const COMMANDS: [u8; 8] = [b'>', b'<', b'+', b'-', b'.', b',', b'[', b']'];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Op {
     Move { d: isize },
     Add { d: isize },
     Out,
     In,
     JmpZ { addr: usize },
     JmpNz { addr: usize },
+    Halt,
+    Dump,
+    Clear,
+    ClearRange { start_off: isize, len: usize },
+    AddAt { off: isize, d: isize },
+    Scan { d: isize },
+    MoveAdd { off: isize },
+    SwapAcc,
+    MulAdd { off: isize, factor: isize },
+    PrintPtr,
+    OutRange { off: isize, pre: i8, step: i8 },
+}
+
+impl Op {
+    /// A fixed numbering of the op kinds along with their operands.
+    pub(crate) fn parts(&self) -> (u8, isize, isize) {
+        match *self {
+            Move { d } => (0, d, 0),
+            Add { d } => (1, d, 0),
+            Out => (2, 0, 0),
+            In => (3, 0, 0),
+            JmpZ { addr } => (4, addr as isize, 0),
+            JmpNz { addr } => (5, addr as isize, 0),
+            Halt => (6, 0, 0),
+            Dump => (7, 0, 0),
+            Clear => (8, 0, 0),
+            ClearRange { start_off, len } => (9, start_off, len as isize),
+            AddAt { off, d } => (10, off, d),
+            Scan { d } => (11, d, 0),
+            MoveAdd { off } => (12, off, 0),
+            SwapAcc => (13, 0, 0),
+            MulAdd { off, factor } => (14, off, factor),
+            PrintPtr => (15, 0, 0),
+            OutRange { off, pre, step } => {
+                (16, off, pre as u8 as isize | (step as u8 as isize) << 8)
+            }
+        }
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Move { d } => write!(f, "move {}", d),
+            Add { d } => write!(f, "add {}", d),
+            Out => write!(f, "out"),
+            In => write!(f, "in"),
+            JmpZ { addr } => write!(f, "jz {}", addr),
+            JmpNz { addr } => write!(f, "jnz {}", addr),
+            Halt => write!(f, "halt"),
+            Dump => write!(f, "dump"),
+            Clear => write!(f, "clear"),
+            ClearRange { start_off, len } => {
+                write!(f, "clear {}..{}", start_off, start_off + len as isize)
+            }
+            AddAt { off, d } => write!(f, "add_at {}, {}", off, d),
+            Scan { d } => write!(f, "scan {}", d),
+            MoveAdd { off } => write!(f, "move_add {}", off),
+            SwapAcc => write!(f, "swap_acc"),
+            MulAdd { off, factor } => write!(f, "mul_add {}, {}", off, factor),
+            PrintPtr => write!(f, "print_ptr"),
+            OutRange { off, pre, step } => write!(f, "out_range {}, {}, {}", off, pre, step),
+        }
+    }
+}
+
+/// The cells `ClearRange { start_off, len }` clears with the pointer at
+/// `ptr`, which may reach off the tape.
+pub(crate) fn cleared_cells(ptr: isize, start_off: isize, len: usize) -> Range<isize> {
+    let start = ptr.saturating_add(start_off);
+    start..start.saturating_add(len as isize)
+}
+
+/// The part of `cells` on a tape of `size` cells.
+fn on_tape_part(cells: Range<isize>, size: usize) -> Range<usize> {
+    let size = size as isize;
+    cells.start.clamp(0, size) as usize..cells.end.clamp(0, size) as usize
 }
 
 #[derive(Debug)]
@@ -21,10 +120,264 @@ struct LeftBracketInfo {
     addr: usize,
 }
 
+/// The line and column of a byte offset, both counted from 1.
+fn position(bytes: &[u8], offset: usize, tab_width: usize) -> (usize, usize) {
+    let before = &bytes[..offset];
+    let line = 1 + before.iter().filter(|&&c| c == b'\n').count();
+    let line_start = before
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |i| i + 1);
+    let col = before[line_start..]
+        .iter()
+        .fold(1, |col, &c| next_col(col, c, tab_width));
+    (line, col)
+}
+
+/// The column after byte `c` at `col`, where a tab moves to the column after
+/// the next multiple of `tab_width`.
+fn next_col(col: usize, c: u8, tab_width: usize) -> usize {
+    match c {
+        b'\t' => col + tab_width - (col - 1) % tab_width,
+        _ => col + 1,
+    }
+}
+
+/// Parses source fed to it in chunks as if it were one slice, carrying
+/// positions, open brackets and folds across the chunk boundaries.
+struct Parser<'a> {
+    options: &'a BuildOptions,
+    ops: &'a mut Vec<Op>,
+    /// The source range of every op, if wanted.
+    spans: Option<&'a mut Vec<Range<usize>>>,
+    jmp_stack: Vec<LeftBracketInfo>,
+    /// The fold of `+-` or `<>` the next byte may extend, and where it began.
+    run: Option<(Op, usize)>,
+    /// Offset of the next byte.
+    pos: usize,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(
+        options: &'a BuildOptions,
+        ops: &'a mut Vec<Op>,
+        spans: Option<&'a mut Vec<Range<usize>>>,
+    ) -> Self {
+        Parser {
+            options,
+            ops,
+            spans,
+            jmp_stack: vec![],
+            run: None,
+            pos: 0,
+            line: 1,
+            col: 1,
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8]) -> Result<(), BuildError> {
+        bytes.iter().try_for_each(|&c| self.push(c))
+    }
+
+    fn push(&mut self, c: u8) -> Result<(), BuildError> {
+        let options = self.options;
+        let step = match c {
+            b'<' => Some(Move { d: -1 }),
+            b'>' => Some(Move { d: 1 }),
+            b'-' => Some(Add { d: -1 }),
+            b'+' => Some(Add { d: 1 }),
+            _ => None,
+        };
+        let fold = options.opt != OptLevel::O0;
+        match (self.run, step) {
+            (Some((Move { d }, start)), Some(Move { d: e })) if fold => {
+                self.run = Some((Move { d: d + e }, start));
+            }
+            (Some((Add { d }, start)), Some(Add { d: e })) if fold => {
+                self.run = Some((Add { d: d + e }, start));
+            }
+            _ => {
+                self.end_run();
+                match step {
+                    Some(op) => self.run = Some((op, self.pos)),
+                    None => self.push_command(c)?,
+                }
+            }
+        }
+        self.col = next_col(self.col, c, options.tab_width.max(1));
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Pushes the fold in progress, unless its moves or adds cancel out.
+    fn end_run(&mut self) {
+        if let Some((op, start)) = self.run.take() {
+            if !matches!(op, Move { d: 0 } | Add { d: 0 }) {
+                self.push_op(op, start..self.pos);
+            }
+        }
+    }
+
+    fn push_command(&mut self, c: u8) -> Result<(), BuildError> {
+        let options = self.options;
+        let op = match c {
+            b'.' => Out,
+            b',' => In,
+            b'@' if options.halt => Halt,
+            b'#' if options.debug => Dump,
+            c if options.swap_acc.is_some_and(|(a, b)| c == a || c == b) => SwapAcc,
+            c if options.print_ptr == Some(c) => PrintPtr,
+            b'[' => {
+                self.jmp_stack.push(LeftBracketInfo {
+                    line: self.line,
+                    col: self.col,
+                    addr: self.ops.len() + 1,
+                });
+                JmpZ { addr: 0 }
+            }
+            b']' => match self.jmp_stack.pop() {
+                Some(info) => {
+                    self.ops[info.addr - 1] = JmpZ {
+                        addr: self.ops.len() + 1,
+                    };
+                    JmpNz { addr: info.addr }
+                }
+                None => return Err(self.error(BuildErrorKind::BracketNotMatch)),
+            },
+            b'\n' => {
+                self.line += 1;
+                self.col = 0;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        self.push_op(op, self.pos..self.pos + 1);
+        Ok(())
+    }
+
+    fn push_op(&mut self, op: Op, span: Range<usize>) {
+        self.ops.push(op);
+        if let Some(spans) = &mut self.spans {
+            spans.push(span);
+        }
+    }
+
+    /// An error at the current position.
+    fn error(&self, kind: BuildErrorKind) -> BuildError {
+        BuildError {
+            line: self.line,
+            col: self.col,
+            kind,
+        }
+    }
+
+    fn finish(mut self) -> Result<(), BuildError> {
+        self.end_run();
+        match self.jmp_stack.pop() {
+            Some(info) => Err(BuildError {
+                line: info.line,
+                col: info.col,
+                kind: BuildErrorKind::BracketNotClosed,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Which optimizations `build` applies.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    /// One op per command, only matching brackets.
+    O0,
+    /// Folds runs of `+-` and `<>`.
+    #[default]
+    O1,
+    /// Also replaces clear, scan, multiply and print loops with single ops, and
+    /// addresses adds relative to the pointer to save moves.
+    O2,
+}
+
+/// Sizes of a program through the stages of `Interpreter::build_with_stats`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildStats {
+    /// Commands in the source, not counting comments.
+    pub commands: usize,
+    /// Ops after folding runs of `+-` and `<>`.
+    pub folded: usize,
+    /// Ops saved by replacing simple loops and loops with known counts.
+    pub loops_removed: usize,
+    /// Ops saved by addressing adds relative to the pointer.
+    pub moves_removed: usize,
+    /// Ops in the built program.
+    pub ops: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// Compile `@` to a halt instruction instead of treating it as a comment.
+    pub halt: bool,
+    /// Compile `#` to a dump of the pointer and nearby cells.
+    pub debug: bool,
+    /// Compile both characters, such as `^` and `v`, to a swap of the
+    /// current cell with a hidden accumulator.
+    pub swap_acc: Option<(u8, u8)>,
+    /// Compile this character to printing the pointer to the debug output.
+    pub print_ptr: Option<u8>,
+    pub opt: OptLevel,
+    /// The `PointerMode` the program will run with. A `Clamp` pointer cannot
+    /// run the ops `OptLevel::O2` makes to reach cells away from the
+    /// pointer, so O2 builds like O1. On a `Wrap` tape two offsets can name
+    /// the same cell, so O2 does not work out loop counts ahead of time.
+    pub pointer_mode: PointerMode,
+    /// Build for cells that do not wrap, as with `CellOverflow::Error` or
+    /// `CellMode::Bit`. The loops `OptLevel::O2` replaces assume they do, so
+    /// O2 builds like O1.
+    pub checked_cells: bool,
+    /// Columns a tab spans in error positions, up to the next multiple of
+    /// this width.
+    pub tab_width: usize,
+}
+
+impl BuildOptions {
+    /// The level the passes run at, which a `Clamp` pointer and
+    /// `checked_cells` lower.
+    fn opt_level(&self) -> OptLevel {
+        match self.opt {
+            OptLevel::O2 if self.pointer_mode == PointerMode::Clamp || self.checked_cells => {
+                OptLevel::O1
+            }
+            opt => opt,
+        }
+    }
+
+    /// Whether two offsets from the pointer may name the same cell.
+    fn wraps(&self) -> bool {
+        self.pointer_mode == PointerMode::Wrap
+    }
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            halt: false,
+            debug: false,
+            swap_acc: None,
+            print_ptr: None,
+            opt: OptLevel::default(),
+            pointer_mode: PointerMode::default(),
+            checked_cells: false,
+            tab_width: 1,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum BuildErrorKind {
     BracketNotMatch,
     BracketNotClosed,
+    IO { err: String },
 }
 
 #[derive(Debug, PartialEq)]
@@ -34,269 +387,3789 @@ pub struct BuildError {
     kind: BuildErrorKind,
 }
 
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            BuildErrorKind::BracketNotMatch => write!(f, "bracket not match")?,
+            BuildErrorKind::BracketNotClosed => write!(f, "bracket not closed")?,
+            BuildErrorKind::IO { err } => write!(f, "io err: {}", err)?,
+        }
+        write!(f, ", line = {}, col = {}", self.line, self.col)
+    }
+}
+
+impl Error for BuildError {}
+
+impl BuildError {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum RuntimeErrorKind {
     DataOverflow { idx: isize },
     IO { err: String },
+    Cancelled,
+    StepLimit { steps: u64 },
+    OutOfGas,
+    CellOverflow { idx: usize, value: isize },
+    Timeout { elapsed: Duration },
+    BadIp { ip: usize },
+    BadRange { start: usize, end: usize },
 }
 
-#[derive(Debug, PartialEq)]
-pub struct RuntimeError {
-    kind: RuntimeErrorKind,
+/// A loop which moves the pointer by `drift` cells every iteration, found by
+/// `Interpreter::lint_drift`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopDrift {
+    /// Position of the loop's `[`.
+    pub line: usize,
+    pub col: usize,
+    pub drift: isize,
 }
 
-impl Display for RuntimeError {
+impl Display for LoopDrift {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match &self.kind {
-            RuntimeErrorKind::DataOverflow { idx } => write!(f, "data overflow, idx = {}", idx),
-            RuntimeErrorKind::IO { err } => write!(f, "io err: {}", err),
-        }
+        write!(
+            f,
+            "loop at line {}, col {} moves the pointer by {} each iteration",
+            self.line, self.col, self.drift
+        )
     }
 }
 
-impl Error for RuntimeError {}
-
-#[derive(Debug)]
-pub struct Interpreter {
-    ops: Vec<Op>,
+/// The cells near the failure when a runtime error happened: around the
+/// index an overflow tried to reach, otherwise around the pointer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapeSnapshot {
+    pub ptr: usize,
+    /// Index of the first cell in `cells`.
+    pub start: usize,
+    pub cells: Vec<u8>,
 }
 
-impl Interpreter {
-    pub fn build(code: &str) -> Result<Self, BuildError> {
-        let bytes = code.as_bytes().iter().map(|c| *c).collect::<Vec<u8>>();
-        let mut result = vec![];
-        let mut line = 1usize;
-        let mut col = 1usize;
-        let mut i = 0;
-        let mut jmp_stack = vec![];
-        while i < bytes.len() {
-            let c = bytes[i];
-            match c {
-                b'<' | b'>' => {
-                    let mut delta = if c == b'<' { -1 } else { 1 };
-                    while i + 1 < bytes.len() && (bytes[i + 1] == b'<' || bytes[i + 1] == b'>') {
-                        delta += if bytes[i + 1] == b'<' { -1 } else { 1 };
-                        i += 1;
-                    }
-                    if delta != 0 {
-                        result.push(Move { d: delta });
-                    }
-                }
-                b'+' | b'-' => {
-                    let mut delta = if c == b'-' { -1 } else { 1 };
-                    while i + 1 < bytes.len() && (bytes[i + 1] == b'-' || bytes[i + 1] == b'+') {
-                        delta += if bytes[i + 1] == b'-' { -1 } else { 1 };
-                        i += 1;
-                    }
-                    if delta != 0 {
-                        result.push(Add { d: delta });
-                    }
-                }
-                b'.' => {
-                    result.push(Out);
-                }
-                b',' => {
-                    result.push(In);
-                }
-                b'[' => {
-                    result.push(JmpZ { addr: 0 });
-                    jmp_stack.push(LeftBracketInfo {
-                        line,
-                        col,
-                        addr: result.len(),
-                    });
-                }
-                b']' => match jmp_stack.pop() {
-                    Some(info) => {
-                        result.push(JmpNz { addr: info.addr });
-                        result[info.addr - 1] = JmpZ { addr: result.len() };
-                    }
-                    None => {
-                        return Err(BuildError {
-                            line,
-                            col,
-                            kind: BuildErrorKind::BracketNotMatch,
-                        });
-                    }
-                },
-                b'\n' => {
-                    line += 1;
-                    col = 0;
-                }
-                _ => {}
+impl TapeSnapshot {
+    fn capture(state: &VmState, kind: &RuntimeErrorKind) -> Self {
+        let around = match *kind {
+            RuntimeErrorKind::DataOverflow { idx } => {
+                idx.clamp(0, state.data.len() as isize - 1) as usize
             }
-            col += 1;
-            i += 1;
+            _ => state.ptr,
+        };
+        let window = window(around, state.data.len());
+        Self {
+            ptr: state.ptr,
+            start: window.start,
+            cells: state.data[window].to_vec(),
         }
+    }
+}
 
-        if let Some(info) = jmp_stack.pop() {
-            return Err(BuildError {
-                line: info.line,
-                col: info.col,
-                kind: BuildErrorKind::BracketNotClosed,
-            });
-        }
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    kind: RuntimeErrorKind,
+    snapshot: Option<TapeSnapshot>,
+    ip: Option<usize>,
+}
 
-        Ok(Self { ops: result })
+impl RuntimeError {
+    pub fn kind(&self) -> &RuntimeErrorKind {
+        &self.kind
     }
 
-    pub fn execute(&self, read: &mut dyn Read, write: &mut dyn Write) -> Result<(), RuntimeError> {
-        let mut data = [0u8; 30000];
-        let mut d_offset = 0usize; // 0~29999
-        let mut i_offset = 0usize;
+    /// The cells near the failure, if `Config::snapshot_on_error` was set.
+    pub fn snapshot(&self) -> Option<&TapeSnapshot> {
+        self.snapshot.as_ref()
+    }
 
-        while i_offset < self.ops.len() {
-            match self.ops[i_offset] {
-                Move { d } => {
-                    if d < 0 && -d as usize > d_offset || d_offset as isize + d >= 30000 {
-                        return Err(RuntimeError {
-                            kind: RuntimeErrorKind::DataOverflow {
-                                idx: d_offset as isize + d,
-                            },
-                        });
-                    }
-                    d_offset = (d_offset as isize + d) as usize;
-                }
-                Add { d } => data[d_offset] = (data[d_offset] as isize + d) as u8,
-                Out => {
-                    write
-                        .write(&data[d_offset..d_offset + 1])
-                        .map_err(|err| RuntimeError {
-                            kind: RuntimeErrorKind::IO {
-                                err: err.to_string(),
-                            },
-                        })?;
-                }
-                In => {
-                    read.read_exact(&mut data[d_offset..d_offset + 1])
-                        .map_err(|err| RuntimeError {
-                            kind: RuntimeErrorKind::IO {
-                                err: err.to_string(),
-                            },
-                        })?;
-                }
-                JmpZ { addr } => {
-                    if data[d_offset] == 0 {
-                        i_offset = addr - 1;
-                    }
-                }
-                JmpNz { addr } => {
-                    if data[d_offset] != 0 {
-                        i_offset = addr - 1;
-                    }
-                }
-            }
+    /// Index of the op that failed, for `Interpreter::reproduction_up_to`,
+    /// or `None` if the error happened outside the program, such as while
+    /// flushing its output.
+    pub fn ip(&self) -> Option<usize> {
+        self.ip
+    }
+}
 
-            i_offset += 1;
+impl From<RuntimeErrorKind> for RuntimeError {
+    fn from(kind: RuntimeErrorKind) -> Self {
+        Self {
+            kind,
+            snapshot: None,
+            ip: None,
         }
-
-        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::VecDeque;
+/// `ptr + d` if it is on a tape of `len` cells, without overflowing on any
+/// pointer width.
+#[inline(always)]
+fn on_tape(ptr: usize, d: isize, len: usize) -> Option<usize> {
+    ptr.checked_add_signed(d).filter(|&idx| idx < len)
+}
 
-    use super::*;
+/// Resolves `ptr + d` outside the tape according to the pointer mode,
+/// growing the tape in `Grow` mode.
+#[cold]
+fn off_tape(
+    ptr: usize,
+    d: isize,
+    data: &mut Vec<u8>,
+    mode: PointerMode,
+    max_tape: usize,
+) -> Result<usize, RuntimeError> {
+    // The tape is shorter than `isize::MAX`, so `ptr` fits.
+    let idx = (ptr as isize).saturating_add(d);
+    let len = data.len();
+    match mode {
+        PointerMode::Grow if idx >= 0 && (idx as usize) < max_tape => {
+            data.resize((idx as usize + 1).max(len * 2).min(max_tape), 0);
+            Ok(idx as usize)
+        }
+        PointerMode::Error | PointerMode::Grow => {
+            Err(RuntimeErrorKind::DataOverflow { idx }.into())
+        }
+        PointerMode::Wrap => Ok(idx.rem_euclid(len as isize) as usize),
+        PointerMode::Clamp => Ok(idx.clamp(0, len as isize - 1) as usize),
+    }
+}
 
-    struct MockInOut {
-        data: VecDeque<u8>,
-        bad: bool,
+/// `off_tape` for ops reaching the cell `off` from the pointer without
+/// moving it. Such ops stand for moves there and back, and a clamped pointer
+/// would have stopped at the edge on the way, so under `Clamp` this is a
+/// `DataOverflow` rather than a guess at the cell.
+#[cold]
+fn off_tape_at(
+    ptr: usize,
+    off: isize,
+    data: &mut Vec<u8>,
+    mode: PointerMode,
+    max_tape: usize,
+) -> Result<usize, RuntimeError> {
+    if mode == PointerMode::Clamp {
+        let idx = (ptr as isize).saturating_add(off);
+        return Err(RuntimeErrorKind::DataOverflow { idx }.into());
     }
+    off_tape(ptr, off, data, mode, max_tape)
+}
 
-    impl MockInOut {
-        fn new(d: Vec<u8>) -> Self {
-            let data = VecDeque::from(d);
-            Self { data, bad: false }
+/// Adds `d` to the cell at `idx`. The result is an error outside the cell's
+/// range unless cells wrap, and saturates at 0 and 1 for bit cells.
+#[inline(always)]
+fn add(
+    data: &mut [u8],
+    idx: usize,
+    d: isize,
+    (mode, overflow): (CellMode, CellOverflow),
+) -> Result<(), RuntimeError> {
+    // Wrapping, since ops from bytecode or asm can add any amount; the
+    // result is the same modulo 256.
+    let value = (data[idx] as isize).wrapping_add(d);
+    match (mode, overflow) {
+        (CellMode::Bit, _) => data[idx] = value.clamp(0, 1) as u8,
+        (CellMode::U8, CellOverflow::Error) if value as usize > 255 => {
+            return Err(RuntimeErrorKind::CellOverflow { idx, value }.into());
         }
-
-        fn dummy() -> Self {
-            Self {
-                data: VecDeque::new(),
-                bad: false,
+        (CellMode::Signed, CellOverflow::Error) => {
+            let value = (data[idx] as i8 as isize).wrapping_add(d);
+            if value as i8 as isize != value {
+                return Err(RuntimeErrorKind::CellOverflow { idx, value }.into());
             }
+            data[idx] = value as u8;
         }
+        _ => data[idx] = value as u8,
+    }
+    Ok(())
+}
 
-        fn bad() -> Self {
-            Self {
-                data: VecDeque::new(),
-                bad: true,
+/// Tries each write again, up to `retries` times, when the writer accepts
+/// nothing.
+struct RetryingWrite<'a, W: ?Sized> {
+    write: &'a mut W,
+    retries: u32,
+}
+
+impl<W: Write + ?Sized> Write for RetryingWrite<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut retries = self.retries;
+        loop {
+            match self.write.write(buf) {
+                Ok(0) if !buf.is_empty() && retries > 0 => retries -= 1,
+                result => return result,
             }
         }
     }
 
-    impl Read for MockInOut {
-        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-            if self.bad {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "read"));
-            }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write.flush()
+    }
+}
 
-            let mut cnt = 0usize;
-            for i in 0..buf.len() {
-                if let Some(c) = self.data.pop_front() {
-                    buf[i] = c;
-                    cnt += 1;
-                } else {
-                    break;
-                }
-            }
-            Ok(cnt)
-        }
+/// Writes what `.` prints for `byte` in `format`.
+#[inline]
+fn write_out<W: Write + ?Sized>(
+    write: &mut W,
+    byte: u8,
+    format: OutputFormat,
+    retries: u32,
+) -> Result<(), RuntimeError> {
+    let mut write = RetryingWrite { write, retries };
+    match format {
+        OutputFormat::Raw => write.write_all(&[byte]),
+        OutputFormat::Decimal => write!(write, "{} ", byte),
     }
+    .map_err(io_err)
+}
 
-    impl Write for MockInOut {
-        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-            if self.bad {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "write"));
-            }
+fn io_err(err: std::io::Error) -> RuntimeError {
+    RuntimeErrorKind::IO {
+        err: err.to_string(),
+    }
+    .into()
+}
 
-            for c in buf {
-                self.data.push_back(*c);
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            RuntimeErrorKind::DataOverflow { idx } => write!(f, "data overflow, idx = {}", idx),
+            RuntimeErrorKind::IO { err } => write!(f, "io err: {}", err),
+            RuntimeErrorKind::Cancelled => write!(f, "cancelled"),
+            RuntimeErrorKind::StepLimit { steps } => {
+                write!(f, "step limit exceeded, steps = {}", steps)
+            }
+            RuntimeErrorKind::OutOfGas => write!(f, "out of gas"),
+            RuntimeErrorKind::CellOverflow { idx, value } => {
+                write!(f, "cell overflow, idx = {}, value = {}", idx, value)
+            }
+            RuntimeErrorKind::Timeout { elapsed } => write!(f, "timed out after {:?}", elapsed),
+            RuntimeErrorKind::BadIp { ip } => {
+                write!(f, "ip past the end of the program, ip = {}", ip)
+            }
+            RuntimeErrorKind::BadRange { start, end } => {
+                write!(f, "bad op range, range = {}..{}", start, end)
             }
-            Ok(buf.len())
-        }
-
-        fn flush(&mut self) -> std::io::Result<()> {
-            Ok(())
         }
     }
+}
 
-    #[test]
-    fn test_basic() {
-        let code = "<+>-.,[]";
-        let interpreter = Interpreter::build(code).unwrap();
-
-        let expected = vec![
-            Move { d: -1 },
-            Add { d: 1 },
-            Move { d: 1 },
-            Add { d: -1 },
-            Out,
-            In,
-            JmpZ { addr: 8 },
-            JmpNz { addr: 7 },
-        ];
+impl Error for RuntimeError {}
 
-        assert_eq!(expected.len(), interpreter.ops.len());
-        for (idx, op) in interpreter.ops.iter().enumerate() {
-            assert_eq!(expected[idx], *op);
-        }
-    }
+/// The cells shown around the pointer by dumps and snapshots.
+fn window(ptr: usize, len: usize) -> Range<usize> {
+    let start = ptr.saturating_sub(8);
+    start..(start + 16).min(len)
+}
 
-    #[test]
-    fn test_fold() {
-        let code = "<><<>><+-++--+<>+-";
-        let interpreter = Interpreter::build(code).unwrap();
+/// The tape, data pointer and instruction pointer of a running program, for
+/// stepping through it with `Interpreter::step`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmState {
+    pub(crate) data: Vec<u8>,
+    pub(crate) ptr: usize,
+    pub(crate) ip: usize,
+    pub(crate) acc: u8,
+    pub(crate) pointer_mode: PointerMode,
+    pub(crate) max_tape: usize,
+    pub(crate) cell_overflow: CellOverflow,
+    pub(crate) cell_mode: CellMode,
+    pub(crate) eof: EofMode,
+    pub(crate) output_format: OutputFormat,
+    pub(crate) write_retries: u32,
+}
 
-        let expected = vec![Move { d: -1 }, Add { d: 1 }];
+impl VmState {
+    pub fn new() -> Self {
+        Self::with_size(TAPE_SIZE)
+    }
 
-        assert_eq!(expected.len(), interpreter.ops.len());
-        for (idx, op) in interpreter.ops.iter().enumerate() {
-            assert_eq!(expected[idx], *op);
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            data: vec![0; size.max(1)],
+            ptr: 0,
+            ip: 0,
+            acc: 0,
+            pointer_mode: PointerMode::default(),
+            max_tape: usize::MAX,
+            cell_overflow: CellOverflow::default(),
+            cell_mode: CellMode::default(),
+            eof: EofMode::default(),
+            output_format: OutputFormat::default(),
+            write_retries: 0,
         }
     }
 
+    /// A fresh state with the tape size and modes of `config`.
+    pub fn with_config(config: &Config) -> Self {
+        let mut state = Self {
+            pointer_mode: config.pointer_mode,
+            max_tape: config.max_tape,
+            cell_overflow: config.cell_overflow,
+            cell_mode: config.cell_mode,
+            eof: config.eof,
+            output_format: config.output_format,
+            write_retries: config.write_retries,
+            ..Self::with_size(config.tape_size)
+        };
+        let n = config.initial_cells.len().min(state.data.len());
+        state.data[..n].copy_from_slice(&config.initial_cells[..n]);
+        state
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The cells read as two's-complement `i8`, as `CellMode::Signed`
+    /// treats them. Adds wrap the same way for signed and unsigned cells,
+    /// and loops only test for zero, so this is just another view of `data`.
+    pub fn signed_data(&self) -> Vec<i8> {
+        self.data.iter().map(|&cell| cell as i8).collect()
+    }
+
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    /// Index of the next op to execute.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    /// The accumulator exchanged with the current cell by `SwapAcc`.
+    pub fn acc(&self) -> u8 {
+        self.acc
+    }
+
+    /// What changed from `self` to `other`. Cells beyond the end of the
+    /// shorter tape count as 0.
+    pub fn diff(&self, other: &VmState) -> StateDiff {
+        let len = self.data.len().max(other.data.len());
+        let cells = (0..len)
+            .map(|idx| {
+                let old = self.data.get(idx).copied().unwrap_or(0);
+                let new = other.data.get(idx).copied().unwrap_or(0);
+                (idx, old, new)
+            })
+            .filter(|(_, old, new)| old != new)
+            .collect();
+        StateDiff {
+            cells,
+            ptr_delta: other.ptr as isize - self.ptr as isize,
+            ip_delta: other.ip as isize - self.ip as isize,
+        }
+    }
+}
+
+impl Default for VmState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Everything a run produced, from `Interpreter::run_capture`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RunResult {
+    pub output: Vec<u8>,
+    /// The whole tape when the program finished.
+    pub tape: Vec<u8>,
+    pub stats: ExecStats,
+}
+
+/// The input of a run and the output it produced, from
+/// `Interpreter::record_run`, to check later runs against.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Recording {
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Recording {
+    /// The input and then the output, each after its length as a
+    /// little-endian `u64`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for part in [&self.input, &self.output] {
+            bytes.extend((part.len() as u64).to_le_bytes());
+            bytes.extend(part);
+        }
+        bytes
+    }
+
+    /// Reads what `to_bytes` wrote, or `None` if `bytes` is malformed.
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<Self> {
+        let mut parts = [vec![], vec![]];
+        for part in &mut parts {
+            let (len, rest) = bytes.split_first_chunk::<8>()?;
+            let len = usize::try_from(u64::from_le_bytes(*len)).ok()?;
+            if rest.len() < len {
+                return None;
+            }
+            *part = rest[..len].to_vec();
+            bytes = &rest[len..];
+        }
+        let [input, output] = parts;
+        bytes.is_empty().then_some(Self { input, output })
+    }
+}
+
+/// The result of `VmState::diff`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StateDiff {
+    /// Index, old value and new value of every changed cell.
+    pub cells: Vec<(usize, u8, u8)>,
+    pub ptr_delta: isize,
+    pub ip_delta: isize,
+}
+
+/// An op which tried to reach cell `idx` off the tape, from
+/// `Interpreter::execute_clamped`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverflowEvent {
+    pub ip: usize,
+    pub idx: isize,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExecStats {
+    /// Number of ops executed.
+    pub steps: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    pub stats: ExecStats,
+    /// Number of distinct cells accessed.
+    pub distinct_cells: usize,
+    /// Indices of the cells written at least once, in order.
+    pub written_cells: Vec<usize>,
+    /// How many times the body of each loop ran, by the index of its
+    /// `JmpZ`. Loops which never ran are left out.
+    pub loop_iterations: BTreeMap<usize, u64>,
+    /// How many cells hold each nonzero value when the program halts.
+    pub histogram: Option<BTreeMap<u8, usize>>,
+}
+
+/// Called by `run` before every op, with the index of the op. Returning
+/// `false` pauses the execution before the op, and an error stops it.
+pub(crate) trait Hook {
+    fn before(
+        &mut self,
+        ip: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        stats: &ExecStats,
+    ) -> Result<bool, RuntimeError>;
+
+    /// Most bytes the op just let run may output, at least 1. An `OutRange`
+    /// stops after that many iterations and stays the next op, so resuming
+    /// runs the rest.
+    #[inline(always)]
+    fn output_limit(&self) -> usize {
+        usize::MAX
+    }
+}
+
+impl Hook for () {
+    #[inline(always)]
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        _: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        Ok(true)
+    }
+}
+
+impl<H: Hook + ?Sized> Hook for &mut H {
+    fn before(
+        &mut self,
+        ip: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        stats: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        (**self).before(ip, op, ptr, data, stats)
+    }
+
+    fn output_limit(&self) -> usize {
+        (**self).output_limit()
+    }
+}
+
+/// Runs both hooks, pausing if either does.
+impl<A: Hook, B: Hook> Hook for (A, B) {
+    fn before(
+        &mut self,
+        ip: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        stats: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        Ok(self.0.before(ip, op, ptr, data, stats)? && self.1.before(ip, op, ptr, data, stats)?)
+    }
+
+    fn output_limit(&self) -> usize {
+        self.0.output_limit().min(self.1.output_limit())
+    }
+}
+
+/// Lets a single op run.
+struct StepHook {
+    stepped: bool,
+}
+
+impl Hook for StepHook {
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        _: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        Ok(!std::mem::replace(&mut self.stepped, true))
+    }
+}
+
+/// Pauses once a jump or the end of the range leaves `range`.
+struct RangeHook {
+    range: Range<usize>,
+}
+
+impl Hook for RangeHook {
+    fn before(
+        &mut self,
+        ip: usize,
+        _: Op,
+        _: usize,
+        _: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        Ok(self.range.contains(&ip))
+    }
+}
+
+/// Records the ops about to reach off the tape, which a clamped pointer
+/// then keeps on it.
+struct OverflowHook {
+    events: Vec<OverflowEvent>,
+}
+
+impl Hook for OverflowHook {
+    fn before(
+        &mut self,
+        ip: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        let len = data.len() as isize;
+        let ptr = ptr as isize;
+        let off_tape = |idx: isize| (idx < 0 || idx >= len).then_some(idx);
+        // Saturating like the index a `DataOverflow` reports, since offsets
+        // decoded from bytecode can be anything.
+        let at = |off: isize| off_tape(ptr.saturating_add(off));
+        let idx = match op {
+            Move { d } | AddAt { off: d, .. } => at(d),
+            MoveAdd { off } | MulAdd { off, .. } | OutRange { off, .. }
+                if data[ptr as usize] != 0 =>
+            {
+                at(off)
+            }
+            Scan { d } if data[ptr as usize] != 0 => at(d),
+            ClearRange { start_off, len } => {
+                let cells = cleared_cells(ptr, start_off, len);
+                off_tape(cells.start).or(off_tape(cells.end - 1))
+            }
+            _ => None,
+        };
+        if let Some(idx) = idx {
+            self.events.push(OverflowEvent { ip, idx });
+        }
+        Ok(true)
+    }
+}
+
+/// Reports each new length of a growing tape, before the op after the one
+/// that grew it.
+struct GrowHook<'a> {
+    len: usize,
+    on_grow: &'a mut dyn FnMut(usize),
+}
+
+impl GrowHook<'_> {
+    fn check(&mut self, data: &[u8]) {
+        if data.len() != self.len {
+            self.len = data.len();
+            (self.on_grow)(self.len);
+        }
+    }
+}
+
+impl Hook for GrowHook<'_> {
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        self.check(data);
+        Ok(true)
+    }
+}
+
+/// Pauses once the watched cell no longer holds `value`.
+struct WatchHook {
+    idx: usize,
+    value: u8,
+}
+
+impl Hook for WatchHook {
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        Ok(data[self.idx] == self.value)
+    }
+}
+
+/// The bytes `op` is about to output in raw format, leaving out those of an
+/// `OutRange` printing a cell off the tape.
+fn output_of(op: Op, ptr: usize, data: &[u8]) -> Vec<u8> {
+    match op {
+        Out => vec![data[ptr]],
+        OutRange { off, pre, step } => match on_tape(ptr, off, data.len()) {
+            Some(idx) => (0..data[ptr] as isize)
+                .map(|k| (data[idx] as isize + pre as isize + k * step as isize) as u8)
+                .collect(),
+            None => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// Stops before the op after an `Out` or `OutRange` whose output ends with
+/// `needle`.
+struct OutputHook<'a> {
+    needle: &'a [u8],
+    tail: Vec<u8>,
+    matched: bool,
+    /// The bytes of the current op up to the one completing `needle`.
+    limit: usize,
+}
+
+impl Hook for OutputHook<'_> {
+    fn before(
+        &mut self,
+        _: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        if self.matched {
+            return Ok(false);
+        }
+        self.limit = usize::MAX;
+        for (i, byte) in output_of(op, ptr, data).into_iter().enumerate() {
+            self.tail.push(byte);
+            if self.tail.len() > self.needle.len() {
+                self.tail.remove(0);
+            }
+            if self.tail == self.needle {
+                self.matched = true;
+                self.limit = i + 1;
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    fn output_limit(&self) -> usize {
+        self.limit
+    }
+}
+
+struct ProfileHook {
+    touched: Vec<bool>,
+    written: Vec<bool>,
+    loop_iterations: BTreeMap<usize, u64>,
+}
+
+impl Hook for ProfileHook {
+    fn before(
+        &mut self,
+        ip: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        match op {
+            JmpZ { .. } if data[ptr] != 0 => *self.loop_iterations.entry(ip).or_insert(0) += 1,
+            JmpNz { addr } if data[ptr] != 0 => {
+                *self.loop_iterations.entry(addr - 1).or_insert(0) += 1
+            }
+            _ => {}
+        }
+        match op {
+            Add { .. } | In | Clear | SwapAcc => self.written[ptr] = true,
+            MoveAdd { .. } | OutRange { .. } if data[ptr] != 0 => self.written[ptr] = true,
+            _ => {}
+        }
+        match op {
+            Move { .. } | Halt | PrintPtr => {}
+            ClearRange { start_off, len } => {
+                let cells = on_tape_part(cleared_cells(ptr as isize, start_off, len), data.len());
+                self.touched[cells.clone()].fill(true);
+                self.written[cells].fill(true);
+            }
+            AddAt { off, .. } => {
+                let idx = ptr as isize + off;
+                if idx >= 0 && idx < data.len() as isize {
+                    self.touched[idx as usize] = true;
+                    self.written[idx as usize] = true;
+                }
+            }
+            Scan { d } => {
+                self.touched[ptr] = true;
+                let idx = ptr as isize + d;
+                if data[ptr] != 0 && idx >= 0 && idx < data.len() as isize {
+                    self.touched[idx as usize] = true;
+                }
+            }
+            MoveAdd { off } | MulAdd { off, .. } | OutRange { off, .. } => {
+                self.touched[ptr] = true;
+                let idx = ptr as isize + off;
+                if data[ptr] != 0 && idx >= 0 && idx < data.len() as isize {
+                    self.touched[idx as usize] = true;
+                    self.written[idx as usize] = true;
+                }
+            }
+            _ => self.touched[ptr] = true,
+        }
+        Ok(true)
+    }
+}
+
+struct LimitHook {
+    max_steps: u64,
+}
+
+impl Hook for LimitHook {
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        _: &[u8],
+        stats: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        if stats.steps >= self.max_steps {
+            return Err(RuntimeErrorKind::StepLimit { steps: stats.steps }.into());
+        }
+        Ok(true)
+    }
+}
+
+/// Stops once `.` has run `remaining` more times.
+struct PreviewHook {
+    remaining: usize,
+    /// What was remaining before the current op.
+    limit: usize,
+}
+
+impl Hook for PreviewHook {
+    fn before(
+        &mut self,
+        _: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        if self.remaining == 0 {
+            return Ok(false);
+        }
+        self.limit = self.remaining;
+        match op {
+            Out => self.remaining -= 1,
+            OutRange { .. } => self.remaining = self.remaining.saturating_sub(data[ptr] as usize),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn output_limit(&self) -> usize {
+        self.limit
+    }
+}
+
+struct GasHook<'a> {
+    gas: u64,
+    schedule: &'a GasSchedule,
+}
+
+impl Hook for GasHook<'_> {
+    fn before(
+        &mut self,
+        _: usize,
+        op: Op,
+        _: usize,
+        _: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        let cost = self.schedule.cost(op);
+        if cost > self.gas {
+            return Err(RuntimeErrorKind::OutOfGas.into());
+        }
+        self.gas -= cost;
+        Ok(true)
+    }
+}
+
+struct TraceHook<'a> {
+    out: &'a mut dyn Write,
+}
+
+impl Hook for TraceHook<'_> {
+    fn before(
+        &mut self,
+        ip: usize,
+        op: Op,
+        ptr: usize,
+        data: &[u8],
+        _: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        writeln!(
+            self.out,
+            "ip = {}, op = {:?}, ptr = {}, cell = {}",
+            ip, op, ptr, data[ptr]
+        )
+        .map_err(io_err)?;
+        Ok(true)
+    }
+}
+
+struct CancelHook {
+    cancel: Arc<AtomicBool>,
+    interval: u64,
+}
+
+impl Hook for CancelHook {
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        _: &[u8],
+        stats: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        if stats.steps.is_multiple_of(self.interval) && self.cancel.load(Ordering::Relaxed) {
+            return Err(RuntimeErrorKind::Cancelled.into());
+        }
+        Ok(true)
+    }
+}
+
+/// Ops between reads of the clock, few enough to check every millisecond or
+/// so on a slow op mix.
+const TIMEOUT_INTERVAL: u64 = 1 << 12;
+
+struct TimeoutHook {
+    start: Instant,
+    timeout: Duration,
+}
+
+impl Hook for TimeoutHook {
+    fn before(
+        &mut self,
+        _: usize,
+        _: Op,
+        _: usize,
+        _: &[u8],
+        stats: &ExecStats,
+    ) -> Result<bool, RuntimeError> {
+        if stats.steps.is_multiple_of(TIMEOUT_INTERVAL) {
+            let elapsed = self.start.elapsed();
+            if elapsed > self.timeout {
+                return Err(RuntimeErrorKind::Timeout { elapsed }.into());
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Re-emits `code` as the shortest equivalent source, without comments and
+/// with runs of `+-` and `<>` cancelled out, so equivalent programs compare
+/// equal.
+pub fn canonicalize(code: &str) -> Result<String, BuildError> {
+    let ops = optimizer::fold(Interpreter::parse(
+        code.as_bytes(),
+        &BuildOptions::default(),
+    )?);
+    Ok(to_source(&ops))
+}
+
+/// Re-emits `code` as the shorter of its canonical form and the source of
+/// its `OptLevel::O2` ops, which drop loops that never run and run counted
+/// ones at build time. Only the eight standard commands are emitted, and the
+/// result does the same with wrapping cells.
+pub fn minify(code: &str) -> Result<String, BuildError> {
+    let canonical = canonicalize(code)?;
+    let optimized = Interpreter::build_opt(code, OptLevel::O2)?;
+    let optimized = canonicalize(&to_source(&optimized.ops))?;
+    Ok(if optimized.len() < canonical.len() {
+        optimized
+    } else {
+        canonical
+    })
+}
+
+/// Commands doing what `ops` do. Optimized ops become the loops they
+/// replaced, `Halt` and `Dump` become `@` and `#`, and swaps and pointer
+/// prints, whose characters are configurable, are left out.
+fn to_source(ops: &[Op]) -> String {
+    fn moved(d: isize) -> String {
+        if d < 0 {
+            "<".repeat(-d as usize)
+        } else {
+            ">".repeat(d as usize)
+        }
+    }
+    fn added(d: isize) -> String {
+        match d.rem_euclid(256) as usize {
+            n if n <= 128 => "+".repeat(n),
+            n => "-".repeat(256 - n),
+        }
+    }
+    fn at(off: isize, commands: &str) -> String {
+        moved(off) + commands + &moved(-off)
+    }
+
+    let mut result = String::new();
+    // The body of a loop replaced by `MulAdd`s, closed at their `Clear`.
+    let mut mul_loop = String::new();
+    for &op in ops {
+        match op {
+            Move { d } => result += &moved(d),
+            Add { d } => result += &added(d),
+            JmpZ { .. } => result.push('['),
+            JmpNz { .. } => result.push(']'),
+            In => result.push(','),
+            Out => result.push('.'),
+            Halt => result.push('@'),
+            Dump => result.push('#'),
+            Clear if !mul_loop.is_empty() => result += &(std::mem::take(&mut mul_loop) + "]"),
+            Clear => result += "[-]",
+            ClearRange { start_off, len } => {
+                let clears = vec!["[-]"; len].join(">");
+                result += &at(start_off, &(clears + &moved(1 - len as isize)));
+            }
+            AddAt { off, d } => result += &at(off, &added(d)),
+            Scan { d } => result += &format!("[{}]", moved(d)),
+            MoveAdd { off } => result += &format!("[-{}]", at(off, "+")),
+            OutRange { off, pre, step } => {
+                let body = added(pre as isize) + "." + &added(step as isize - pre as isize);
+                result += &format!("[{}-]", at(off, &body));
+            }
+            MulAdd { off, factor } => {
+                if mul_loop.is_empty() {
+                    mul_loop.push_str("[-");
+                }
+                mul_loop += &at(off, &added(factor));
+            }
+            SwapAcc | PrintPtr => {}
+        }
+    }
+    if !mul_loop.is_empty() {
+        result += &(mul_loop + "]");
+    }
+    result
+}
+
+/// Runs both programs on `input` and returns the index of the first output
+/// byte where they differ, counting a missing byte when one output is
+/// shorter. Output written before a runtime error is compared too.
+pub fn diff_output(a: &Interpreter, b: &Interpreter, input: &[u8]) -> Option<usize> {
+    let output = |inter: &Interpreter| {
+        let mut output = vec![];
+        let _ = inter.run(
+            &mut VmState::new(),
+            &mut &input[..],
+            &mut output,
+            &mut sink(),
+        );
+        output
+    };
+    let (a, b) = (output(a), output(b));
+    (0..a.len().max(b.len())).find(|&idx| a.get(idx) != b.get(idx))
+}
+
+/// Whether both programs write the same output for each of `inputs`, and
+/// either both finish or both fail, running at most `max_steps` ops each.
+/// Passing does not prove the programs equivalent on other inputs.
+pub fn io_equivalent(a: &Interpreter, b: &Interpreter, inputs: &[Vec<u8>], max_steps: u64) -> bool {
+    let run = |inter: &Interpreter, input: &[u8]| {
+        let mut output = vec![];
+        let ok = inter
+            .execute_limited(&mut &input[..], &mut output, max_steps)
+            .is_ok();
+        (output, ok)
+    };
+    inputs.iter().all(|input| run(a, input) == run(b, input))
+}
+
+/// Writes into an output buffer shared with a `FlushingRead`.
+struct SharedWrite<'a, W: Write>(&'a RefCell<W>);
+
+impl<W: Write> Write for SharedWrite<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Flushes the buffered output before every read, so prompts are shown
+/// before the program waits for input.
+struct FlushingRead<'a, R: ?Sized, W: Write> {
+    read: &'a mut R,
+    out: &'a RefCell<W>,
+}
+
+impl<R: Read + ?Sized, W: Write> Read for FlushingRead<'_, R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.out.borrow_mut().flush()?;
+        self.read.read(buf)
+    }
+}
+
+/// Counts the bytes written to it and drops them.
+struct CountingWrite(u64);
+
+impl Write for CountingWrite {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads one byte at a time from a callback, `None` meaning the end of input.
+struct FnInput<'a>(&'a mut dyn FnMut() -> Option<u8>);
+
+impl Read for FnInput<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match (self.0)() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+/// Which of two cells may be nonzero at a point of a program, for
+/// `Interpreter::may_both_be_nonzero`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CellPair {
+    cells: [isize; 2],
+    /// The pointer, unless it depends on the tape.
+    ptr: Option<isize>,
+    nonzero: [bool; 2],
+}
+
+impl CellPair {
+    /// Records a write of the cell `off` from the pointer, which may hit
+    /// either cell if the pointer is unknown.
+    fn set(&mut self, off: isize, nonzero: bool) {
+        for i in 0..2 {
+            match self.ptr {
+                Some(ptr) if ptr + off == self.cells[i] => self.nonzero[i] = nonzero,
+                Some(_) => {}
+                None => self.nonzero[i] |= nonzero,
+            }
+        }
+    }
+
+    fn both(&self) -> bool {
+        self.nonzero == [true, true]
+    }
+
+    /// Follows `ops`, returning `true` as soon as both cells may be nonzero.
+    /// Loops are followed until their state stops changing, since they can
+    /// run any number of times.
+    fn run(&mut self, ops: &[Op]) -> bool {
+        let mut i = 0;
+        while i < ops.len() {
+            match ops[i] {
+                Move { d } => self.ptr = self.ptr.map(|ptr| ptr + d),
+                Add { .. } | In | SwapAcc => self.set(0, true),
+                AddAt { off, .. } | MulAdd { off, .. } => self.set(off, true),
+                Clear => self.set(0, false),
+                ClearRange { start_off, len } => {
+                    for off in cleared_cells(0, start_off, len) {
+                        self.set(off, false);
+                    }
+                }
+                MoveAdd { off } | OutRange { off, .. } => {
+                    self.set(off, true);
+                    if self.both() {
+                        return true;
+                    }
+                    self.set(0, false);
+                }
+                Scan { .. } => self.ptr = None,
+                JmpZ { addr } => {
+                    let body = &ops[i + 1..addr - 1];
+                    loop {
+                        let mut after = *self;
+                        if after.run(body) {
+                            return true;
+                        }
+                        let joined = Self {
+                            cells: self.cells,
+                            ptr: self.ptr.filter(|&ptr| after.ptr == Some(ptr)),
+                            nonzero: [0, 1].map(|i| self.nonzero[i] | after.nonzero[i]),
+                        };
+                        if joined == *self {
+                            break;
+                        }
+                        *self = joined;
+                    }
+                    self.set(0, false);
+                    i = addr;
+                    continue;
+                }
+                Out | JmpNz { .. } | Halt | Dump | PrintPtr => {}
+            }
+            if self.both() {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct Interpreter {
+    ops: Vec<Op>,
+    /// Notes about how the program was built, such as its source length,
+    /// which never change how it runs.
+    metadata: BTreeMap<String, String>,
+}
+
+/// Rebuilds a program after edits, only parsing the source again from the
+/// first changed byte.
+#[derive(Debug, Default)]
+pub struct IncrementalBuilder {
+    options: BuildOptions,
+    ops: Vec<Op>,
+    spans: Vec<Range<usize>>,
+    reused: usize,
+}
+
+impl IncrementalBuilder {
+    pub fn new(options: BuildOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Builds `code`, whose first `edit` bytes are the same as in the code of
+    /// the previous call.
+    pub fn rebuild(&mut self, code: &str, edit: usize) -> Result<Interpreter, BuildError> {
+        // An op ending right at the edit could be extended by it, e.g. `++`
+        // followed by an inserted `+`.
+        let keep = self.spans.iter().take_while(|span| span.end < edit).count();
+        self.ops.truncate(keep);
+        self.spans.truncate(keep);
+        self.reused = keep;
+        Interpreter::parse_into(
+            code.as_bytes(),
+            &self.options,
+            &mut self.ops,
+            &mut self.spans,
+        )?;
+        let opt = self.options.opt_level();
+        let wrap = self.options.wraps();
+        let ops = optimizer::optimize(self.ops.clone(), opt, wrap, &mut BuildStats::default());
+        Ok(Interpreter::from_source(ops, code.len(), opt))
+    }
+
+    /// Number of parsed ops the last `rebuild` kept from the one before.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+}
+
+impl Interpreter {
+    /// Wraps built ops, recording their count in the metadata.
+    fn from_ops(ops: Vec<Op>) -> Self {
+        let metadata = BTreeMap::from([("ops".to_string(), ops.len().to_string())]);
+        Self { ops, metadata }
+    }
+
+    /// Like `from_ops`, also recording the length of the source and the
+    /// `OptLevel` the ops were built with.
+    fn from_source(ops: Vec<Op>, source_len: usize, opt: OptLevel) -> Self {
+        #[cfg(feature = "log")]
+        log::trace!(
+            "built {} ops from {} bytes at {:?}",
+            ops.len(),
+            source_len,
+            opt
+        );
+        let mut inter = Self::from_ops(ops);
+        inter.set_metadata("source_len", &source_len.to_string());
+        inter.set_metadata("opt", &format!("{:?}", opt));
+        inter
+    }
+
+    pub fn build(code: &str) -> Result<Self, BuildError> {
+        Self::build_with(code, &BuildOptions::default())
+    }
+
+    pub fn build_opt(code: &str, opt: OptLevel) -> Result<Self, BuildError> {
+        Self::build_with(
+            code,
+            &BuildOptions {
+                opt,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn build_with(code: &str, options: &BuildOptions) -> Result<Self, BuildError> {
+        Self::build_bytes_with(code.as_bytes(), options)
+    }
+
+    /// Like `build`, for sources which are not valid UTF-8, such as files
+    /// with stray bytes in comments.
+    pub fn build_bytes(code: &[u8]) -> Result<Self, BuildError> {
+        Self::build_bytes_with(code, &BuildOptions::default())
+    }
+
+    pub fn build_bytes_with(code: &[u8], options: &BuildOptions) -> Result<Self, BuildError> {
+        let opt = options.opt_level();
+        let ops = Self::parse(code, options)?;
+        let ops = optimizer::optimize(ops, opt, options.wraps(), &mut BuildStats::default());
+        Ok(Self::from_source(ops, code.len(), opt))
+    }
+
+    /// Like `build_bytes_with`, parsing the source as it is read from a
+    /// stream, so only the ops are kept in memory. A failed read is reported
+    /// at the position it stopped at.
+    pub fn build_from_reader(
+        mut reader: impl Read,
+        options: &BuildOptions,
+    ) -> Result<Self, BuildError> {
+        #[cfg(feature = "log")]
+        log::trace!("building from a stream at {:?}", options.opt_level());
+        let mut ops = vec![];
+        let mut parser = Parser::new(options, &mut ops, None);
+        let mut chunk = [0; 8192];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => parser.feed(&chunk[..n])?,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    return Err(parser.error(BuildErrorKind::IO {
+                        err: err.to_string(),
+                    }))
+                }
+            }
+        }
+        let source_len = parser.pos;
+        parser.finish()?;
+        let opt = options.opt_level();
+        let ops = optimizer::optimize(ops, opt, options.wraps(), &mut BuildStats::default());
+        Ok(Self::from_source(ops, source_len, opt))
+    }
+
+    /// Like `build_with`, also reporting how much each stage shrank the
+    /// program.
+    pub fn build_with_stats(
+        code: &str,
+        options: &BuildOptions,
+    ) -> Result<(Self, BuildStats), BuildError> {
+        let commands = code
+            .bytes()
+            .filter(|&c| match c {
+                c if COMMANDS.contains(&c) => true,
+                b'@' => options.halt,
+                b'#' => options.debug,
+                c => {
+                    options.swap_acc.is_some_and(|(a, b)| c == a || c == b)
+                        || options.print_ptr == Some(c)
+                }
+            })
+            .count();
+        let mut stats = BuildStats {
+            commands,
+            ..Default::default()
+        };
+        let opt = options.opt_level();
+        let ops = Self::parse(code.as_bytes(), options)?;
+        let ops = optimizer::optimize(ops, opt, options.wraps(), &mut stats);
+        stats.ops = ops.len();
+        Ok((Self::from_source(ops, code.len(), opt), stats))
+    }
+
+    pub(crate) fn parse(code: &[u8], options: &BuildOptions) -> Result<Vec<Op>, BuildError> {
+        #[cfg(feature = "log")]
+        log::trace!("building {} bytes at {:?}", code.len(), options.opt_level());
+        let mut ops = vec![];
+        let mut parser = Parser::new(options, &mut ops, None);
+        parser.feed(code)?;
+        parser.finish()?;
+        Ok(ops)
+    }
+
+    /// Parses the rest of `code` after the ops already in `result`, recording
+    /// the source range of every op in `spans`.
+    fn parse_into(
+        bytes: &[u8],
+        options: &BuildOptions,
+        result: &mut Vec<Op>,
+        spans: &mut Vec<Range<usize>>,
+    ) -> Result<(), BuildError> {
+        let i = spans.last().map_or(0, |span| span.end);
+        let tab_width = options.tab_width.max(1);
+        let (line, col) = position(bytes, i, tab_width);
+        let mut jmp_stack = vec![];
+        for (idx, op) in result.iter().enumerate() {
+            match op {
+                JmpZ { .. } => {
+                    let (line, col) = position(bytes, spans[idx].start, tab_width);
+                    jmp_stack.push(LeftBracketInfo {
+                        line,
+                        col,
+                        addr: idx + 1,
+                    });
+                }
+                JmpNz { .. } => {
+                    jmp_stack.pop();
+                }
+                _ => {}
+            }
+        }
+        let mut parser = Parser {
+            jmp_stack,
+            pos: i,
+            line,
+            col,
+            ..Parser::new(options, result, Some(spans))
+        };
+        parser.feed(&bytes[i..])?;
+        parser.finish()
+    }
+
+    /// A hash of the compiled ops which is stable across runs and platforms,
+    /// so sources compiling to the same ops share a fingerprint.
+    pub fn fingerprint(&self) -> u64 {
+        // FNV-1a
+        let mut hash = 0xcbf29ce484222325u64;
+        for op in &self.ops {
+            let (code, a, b) = op.parts();
+            let mut bytes = vec![code];
+            bytes.extend_from_slice(&(a as i64).to_le_bytes());
+            bytes.extend_from_slice(&(b as i64).to_le_bytes());
+            for byte in bytes {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// A compact, platform independent encoding of the compiled ops.
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        let mut bytes = bytecode::encode(&self.ops);
+        bytecode::encode_metadata(&mut bytes, &self.metadata);
+        bytes
+    }
+
+    /// Reads back the ops and metadata of `to_bytecode`. Bytecode without
+    /// metadata only records its op count.
+    pub fn from_bytecode(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let (ops, metadata) = bytecode::decode(bytes)?;
+        let mut inter = Self::from_ops(ops);
+        inter.metadata.extend(metadata);
+        Ok(inter)
+    }
+
+    /// The ops as text, one per line like `add_at 2, 3`, with each loop's
+    /// `jz` and `jnz` naming the same label. `from_asm` reads it back.
+    pub fn to_asm(&self) -> String {
+        asm::format(&self.ops)
+    }
+
+    /// The ops as a JSON array of objects, each with the op's name under
+    /// `"op"` and its operands by field name, like
+    /// `{"op":"add_at","off":2,"d":3}`. Jumps have the index they jump to
+    /// under `"addr"`.
+    pub fn to_json(&self) -> String {
+        let ops: Vec<String> = self
+            .ops
+            .iter()
+            .map(|op| {
+                let (name, fields): (_, &[(&str, isize)]) = match *op {
+                    Move { d } => ("move", &[("d", d)]),
+                    Add { d } => ("add", &[("d", d)]),
+                    Out => ("out", &[]),
+                    In => ("in", &[]),
+                    JmpZ { addr } => ("jz", &[("addr", addr as isize)]),
+                    JmpNz { addr } => ("jnz", &[("addr", addr as isize)]),
+                    Halt => ("halt", &[]),
+                    Dump => ("dump", &[]),
+                    Clear => ("clear", &[]),
+                    ClearRange { start_off, len } => (
+                        "clear_range",
+                        &[("start_off", start_off), ("len", len as isize)],
+                    ),
+                    AddAt { off, d } => ("add_at", &[("off", off), ("d", d)]),
+                    Scan { d } => ("scan", &[("d", d)]),
+                    MoveAdd { off } => ("move_add", &[("off", off)]),
+                    SwapAcc => ("swap_acc", &[]),
+                    MulAdd { off, factor } => ("mul_add", &[("off", off), ("factor", factor)]),
+                    PrintPtr => ("print_ptr", &[]),
+                    OutRange { off, pre, step } => (
+                        "out_range",
+                        &[("off", off), ("pre", pre as isize), ("step", step as isize)],
+                    ),
+                };
+                let fields: String = fields
+                    .iter()
+                    .map(|(field, value)| format!(",\"{}\":{}", field, value))
+                    .collect();
+                format!("{{\"op\":\"{}\"{}}}", name, fields)
+            })
+            .collect();
+        format!("[{}]", ops.join(","))
+    }
+
+    /// Builds a program from ops written as text, such as by `to_asm`, so
+    /// optimized ops can be written by hand.
+    pub fn from_asm(text: &str) -> Result<Self, AsmError> {
+        Ok(Self::from_ops(asm::parse(text)?))
+    }
+
+    /// Runs the `OptLevel::O2` passes again on the current ops, such as
+    /// those decoded from bytecode written by another tool, for a pointer
+    /// that does not wrap.
+    pub fn optimize(&mut self) {
+        let ops = std::mem::take(&mut self.ops);
+        self.ops = optimizer::optimize(ops, OptLevel::O2, false, &mut BuildStats::default());
+        self.set_metadata("ops", &self.ops.len().to_string());
+        self.set_metadata("opt", "O2");
+    }
+
+    /// Indices of the ops no path of branches from the first op leads to,
+    /// such as those after an `@` halt outside any loop.
+    pub fn unreachable_ops(&self) -> Vec<usize> {
+        let mut reached = vec![false; self.ops.len()];
+        let mut pending = vec![0];
+        while let Some(ip) = pending.pop() {
+            if ip >= self.ops.len() || reached[ip] {
+                continue;
+            }
+            reached[ip] = true;
+            match self.ops[ip] {
+                JmpZ { addr } | JmpNz { addr } => pending.extend([addr, ip + 1]),
+                Halt => {}
+                _ => pending.push(ip + 1),
+            }
+        }
+        (0..self.ops.len()).filter(|&ip| !reached[ip]).collect()
+    }
+
+    /// The output of a program that reads no input, worked out from its ops
+    /// on a fresh tape without running its loops. `None` unless every loop
+    /// is skipped or has a count known from the cells, like those the
+    /// optimizer runs at build time, and the pointer stays on the tape.
+    pub fn output_is_constant(&self) -> Option<Vec<u8>> {
+        let mut data = vec![0u8; TAPE_SIZE];
+        let mut output = vec![];
+        let mut ptr = 0isize;
+        let mut ip = 0;
+        let cell = |idx: isize| usize::try_from(idx).ok().filter(|&idx| idx < TAPE_SIZE);
+        let add = |data: &mut [u8], idx: isize, d: isize| -> Option<()> {
+            let idx = cell(idx)?;
+            data[idx] = (data[idx] as isize).wrapping_add(d) as u8;
+            Some(())
+        };
+        while ip < self.ops.len() {
+            let value = data[ptr as usize] as isize;
+            match self.ops[ip] {
+                Move { d } => {
+                    ptr = ptr.checked_add(d)?;
+                    cell(ptr)?;
+                }
+                Add { d } => add(&mut data, ptr, d)?,
+                AddAt { off, d } => add(&mut data, ptr.checked_add(off)?, d)?,
+                Out => output.push(value as u8),
+                Clear => data[ptr as usize] = 0,
+                ClearRange { start_off, len } => {
+                    for idx in cleared_cells(ptr, start_off, len) {
+                        data[cell(idx)?] = 0;
+                    }
+                }
+                MoveAdd { off } => {
+                    add(&mut data, ptr.checked_add(off)?, value)?;
+                    data[ptr as usize] = 0;
+                }
+                MulAdd { off, factor } => {
+                    add(&mut data, ptr.checked_add(off)?, value.wrapping_mul(factor))?
+                }
+                OutRange { off, pre, step } => {
+                    let idx = cell(ptr.checked_add(off)?)?;
+                    for _ in 0..value {
+                        output.push((data[idx] as isize + pre as isize) as u8);
+                        data[idx] = (data[idx] as isize + step as isize) as u8;
+                    }
+                    data[ptr as usize] = 0;
+                }
+                JmpZ { addr } if value == 0 => {
+                    ip = addr;
+                    continue;
+                }
+                JmpZ { addr } => {
+                    let adds = optimizer::loop_effects(&self.ops[ip + 1..addr - 1])?;
+                    let step = adds.iter().find(|&&(off, _)| off == 0)?.1;
+                    if step >= 0 || value % step != 0 {
+                        return None;
+                    }
+                    for (off, d) in adds {
+                        add(
+                            &mut data,
+                            ptr.checked_add(off)?,
+                            d.wrapping_mul(value / -step),
+                        )?;
+                    }
+                    ip = addr;
+                    continue;
+                }
+                Halt => break,
+                Dump | PrintPtr => {}
+                In | JmpNz { .. } | Scan { .. } | SwapAcc => return None,
+            }
+            ip += 1;
+        }
+        Some(output)
+    }
+
+    /// Whether cells `a` and `b` can both be nonzero at once in a run on a
+    /// fresh tape. This over-approximates: any write while the pointer's
+    /// position depends on the tape, such as after `[>]`, counts for both
+    /// cells, so `false` means the cells are never both nonzero.
+    pub fn may_both_be_nonzero(&self, a: usize, b: usize) -> bool {
+        let mut pair = CellPair {
+            cells: [a as isize, b as isize],
+            ptr: Some(0),
+            nonzero: [false; 2],
+        };
+        pair.run(&self.ops)
+    }
+
+    /// Number of loops, not counting those optimized into a single op such
+    /// as `[-]`.
+    pub fn loop_count(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|op| matches!(op, JmpZ { .. }))
+            .count()
+    }
+
+    /// How deeply loops are nested, 0 for a program without loops.
+    pub fn max_loop_depth(&self) -> usize {
+        let mut depth = 0usize;
+        let mut max = 0;
+        for op in &self.ops {
+            match op {
+                JmpZ { .. } => {
+                    depth += 1;
+                    max = max.max(depth);
+                }
+                JmpNz { .. } => depth -= 1,
+                _ => {}
+            }
+        }
+        max
+    }
+
+    /// Returns whether the program reads input and whether it writes output.
+    pub fn does_io(&self) -> (bool, bool) {
+        self.ops.iter().fold((false, false), |(reads, writes), op| {
+            (
+                reads || *op == In,
+                writes || matches!(op, Out | OutRange { .. }),
+            )
+        })
+    }
+
+    /// Returns whether the program has no jumps, so it runs each op once.
+    /// Loops the optimizer replaced with single ops, like `[-]` at
+    /// `OptLevel::O2`, do not count.
+    pub fn is_straight_line(&self) -> bool {
+        !self
+            .ops
+            .iter()
+            .any(|op| matches!(op, JmpZ { .. } | JmpNz { .. }))
+    }
+
+    /// The total each cell gets added, keyed by its offset from the first
+    /// cell, for a program of only moves and adds. `None` for any other
+    /// program, such as one with loops or I/O, or if a total does not fit.
+    pub fn net_effect(&self) -> Option<BTreeMap<isize, i16>> {
+        let mut totals = BTreeMap::<isize, isize>::new();
+        let mut ptr = 0isize;
+        for op in &self.ops {
+            let (off, d) = match *op {
+                Move { d } => {
+                    ptr = ptr.checked_add(d)?;
+                    continue;
+                }
+                Add { d } => (0, d),
+                AddAt { off, d } => (off, d),
+                _ => return None,
+            };
+            let total = totals.entry(ptr.checked_add(off)?).or_insert(0);
+            *total = total.checked_add(d)?;
+        }
+        totals
+            .into_iter()
+            .filter(|&(_, total)| total != 0)
+            .map(|(off, total)| Some((off, i16::try_from(total).ok()?)))
+            .collect()
+    }
+
+    /// Lists the ops one per line after their address, which jumps refer to.
+    pub fn disassemble(&self) -> String {
+        self.ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| format!("{:>5}  {}\n", i, op))
+            .collect()
+    }
+
+    /// Encodes the ops as `(opcode, operand)` pairs for hosts that cannot read
+    /// Rust types. The opcodes are:
+    ///
+    /// | opcode | op | operand |
+    /// |---|---|---|
+    /// | 0 | move | distance |
+    /// | 1 | add | amount |
+    /// | 2 | out | |
+    /// | 3 | in | |
+    /// | 4 | jump if zero | target index |
+    /// | 5 | jump if nonzero | target index |
+    /// | 6 | halt | |
+    /// | 7 | dump | |
+    /// | 8 | clear | |
+    /// | 9 | clear range | start offset, length |
+    /// | 10 | add at offset | offset, amount |
+    /// | 11 | scan for zero | step |
+    /// | 12 | move and add | offset |
+    /// | 13 | swap with accumulator | |
+    /// | 14 | multiply and add | offset, factor |
+    /// | 15 | print pointer | |
+    /// | 16 | print a range | offset, first add and step as `pre + (step << 8)` |
+    ///
+    /// Ops with two operands pack the first in the high 32 bits and the
+    /// second in the low 32 bits as `(first << 32) | (second as u32)`; the
+    /// others' operand is 0 when they take none.
+    pub fn instruction_table(&self) -> Vec<(u8, i64)> {
+        self.ops
+            .iter()
+            .map(|op| match op.parts() {
+                (code @ (9 | 10 | 14 | 16), a, b) => (code, (a as i64) << 32 | b as u32 as i64),
+                (code, a, _) => (code, a as i64),
+            })
+            .collect()
+    }
+
+    /// The source of ops `0..=ip`, or of all of them if `ip` is past the
+    /// end, with the loops still open at `ip` closed so it builds. With the
+    /// `ip` of a runtime error, this trims a failing program down to a
+    /// reproducer for a bug report.
+    pub fn reproduction_up_to(&self, ip: usize) -> String {
+        let ops = &self.ops[..ip.saturating_add(1).min(self.ops.len())];
+        let open = ops.iter().filter(|op| matches!(op, JmpZ { .. })).count()
+            - ops.iter().filter(|op| matches!(op, JmpNz { .. })).count();
+        to_source(ops) + &"]".repeat(open)
+    }
+
+    /// Finds the loops in `code` whose body leaves the pointer somewhere else
+    /// than it started. This is often a bug, but also how loops like `[>]`
+    /// search the tape, so the results are only warnings. A loop containing
+    /// a drifting loop is not listed itself.
+    pub fn lint_drift(code: &str) -> Result<Vec<LoopDrift>, BuildError> {
+        let mut ops = vec![];
+        let mut spans = vec![];
+        Self::parse_into(
+            code.as_bytes(),
+            &BuildOptions::default(),
+            &mut ops,
+            &mut spans,
+        )?;
+        let mut drifts = vec![];
+        for (i, op) in ops.iter().enumerate() {
+            if let JmpZ { addr } = *op {
+                match optimizer::net_move(&ops[i + 1..addr - 1]) {
+                    Some(0) | None => {}
+                    Some(drift) => {
+                        let (line, col) = position(code.as_bytes(), spans[i].start, 1);
+                        drifts.push(LoopDrift { line, col, drift });
+                    }
+                }
+            }
+        }
+        Ok(drifts)
+    }
+
+    /// How many times each of the eight commands appears in `code`, leaving
+    /// out those which do not.
+    pub fn command_histogram(code: &str) -> BTreeMap<char, usize> {
+        let mut histogram = BTreeMap::new();
+        for c in code.bytes().filter(|c| COMMANDS.contains(c)) {
+            *histogram.entry(c as char).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Describes the commands in `code[start..end]` in words, with runs of
+    /// `+-` and `<>` folded, e.g. "move right 3, add 5, loop while nonzero".
+    /// The range does not need balanced brackets. It is cut at the end of
+    /// `code`, and one starting after its end describes nothing.
+    pub fn explain_range(code: &str, start: usize, end: usize) -> String {
+        let code = code.as_bytes();
+        let range = code.get(start..end.min(code.len())).unwrap_or_default();
+        let ops = range.iter().filter_map(|c| match c {
+            b'<' => Some(Move { d: -1 }),
+            b'>' => Some(Move { d: 1 }),
+            b'-' => Some(Add { d: -1 }),
+            b'+' => Some(Add { d: 1 }),
+            b'.' => Some(Out),
+            b',' => Some(In),
+            b'[' => Some(JmpZ { addr: 0 }),
+            b']' => Some(JmpNz { addr: 0 }),
+            _ => None,
+        });
+        let steps: Vec<String> = optimizer::fold(ops.collect())
+            .into_iter()
+            .map(|op| match op {
+                Move { d } if d < 0 => format!("move left {}", -d),
+                Move { d } => format!("move right {}", d),
+                Add { d } if d < 0 => format!("subtract {}", -d),
+                Add { d } => format!("add {}", d),
+                Out => "output the cell".to_string(),
+                In => "read into the cell".to_string(),
+                JmpZ { .. } => "loop while nonzero".to_string(),
+                JmpNz { .. } => "end loop".to_string(),
+                _ => unreachable!("not produced by parsing"),
+            })
+            .collect();
+        steps.join(", ")
+    }
+
+    /// Splits the ops into straight runs: each run starts the program or
+    /// follows or is the target of a jump, and ends at the next jump or
+    /// halt, which it includes.
+    pub fn basic_blocks(&self) -> Vec<Range<usize>> {
+        let len = self.ops.len();
+        let mut leaders = BTreeSet::from([0]);
+        for (i, op) in self.ops.iter().enumerate() {
+            if let JmpZ { addr } | JmpNz { addr } = *op {
+                leaders.insert(addr);
+                leaders.insert(i + 1);
+            } else if *op == Halt {
+                leaders.insert(i + 1);
+            }
+        }
+        let leaders: Vec<usize> = leaders.into_iter().filter(|&i| i < len).collect();
+        leaders
+            .iter()
+            .enumerate()
+            .map(|(n, &start)| start..leaders.get(n + 1).copied().unwrap_or(len))
+            .collect()
+    }
+
+    /// Renders the control flow as a Graphviz graph, with a node for each
+    /// straight run of ops and an edge for each way out of it.
+    pub fn to_dot(&self) -> String {
+        let len = self.ops.len();
+        let node = |i: usize| {
+            if i < len {
+                format!("n{}", i)
+            } else {
+                "exit".to_string()
+            }
+        };
+
+        let mut dot = String::from("digraph program {\n    node [shape=box];\n");
+        dot += "    exit [shape=doublecircle];\n";
+        let mut edges = String::new();
+        for Range { start, end } in self.basic_blocks() {
+            let label: Vec<String> = self.ops[start..end]
+                .iter()
+                .map(|op| format!("{:?}", op))
+                .collect();
+            dot += &format!("    {} [label=\"{}\"];\n", node(start), label.join("\\n"));
+
+            let from = node(start);
+            match self.ops[end - 1] {
+                JmpZ { addr } => {
+                    edges += &format!("    {} -> {} [label=\"zero\"];\n", from, node(addr));
+                    edges += &format!("    {} -> {} [label=\"nonzero\"];\n", from, node(end));
+                }
+                JmpNz { addr } => {
+                    edges += &format!("    {} -> {} [label=\"nonzero\"];\n", from, node(addr));
+                    edges += &format!("    {} -> {} [label=\"zero\"];\n", from, node(end));
+                }
+                Halt => edges += &format!("    {} -> exit;\n", from),
+                _ => edges += &format!("    {} -> {};\n", from, node(end)),
+            }
+        }
+        dot + &edges + "}\n"
+    }
+
+    pub fn execute(&self, read: &mut dyn Read, write: &mut dyn Write) -> Result<(), RuntimeError> {
+        self.execute_with(read, write)
+    }
+
+    /// Like `execute`, but monomorphized for the reader and writer so that
+    /// I/O calls can be inlined.
+    pub fn execute_with<R: Read + ?Sized, W: Write + ?Sized>(
+        &self,
+        read: &mut R,
+        write: &mut W,
+    ) -> Result<(), RuntimeError> {
+        self.run(&mut VmState::new(), read, write, &mut stderr())
+            .map(|_| ())
+    }
+
+    pub fn execute_with_config<R: Read + ?Sized, W: Write + ?Sized>(
+        &self,
+        read: &mut R,
+        write: &mut W,
+        config: &Config,
+    ) -> Result<ExecStats, RuntimeError> {
+        let mut state = VmState::with_config(config);
+        self.run_config(&mut state, read, write, config, &mut ())
+    }
+
+    /// Like `execute_with_config`, on a `state` from `VmState::with_config`
+    /// which holds the final tape afterwards.
+    pub fn execute_with_state(
+        &self,
+        state: &mut VmState,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        config: &Config,
+    ) -> Result<ExecStats, RuntimeError> {
+        self.run_config(state, read, write, config, &mut ())
+    }
+
+    /// Like `execute_with_state`, also writing the op index, op, pointer
+    /// and current cell to `trace` before every op.
+    pub fn execute_traced(
+        &self,
+        state: &mut VmState,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        config: &Config,
+        trace: &mut dyn Write,
+    ) -> Result<ExecStats, RuntimeError> {
+        self.run_config(state, read, write, config, &mut TraceHook { out: trace })
+    }
+
+    /// Runs to completion from op `ip` with `tape` as the cells and the
+    /// pointer on cell 0, to resume a program saved as just its tape and op
+    /// index. `ip` counts compiled ops, as listed by `disassemble`, and
+    /// past the end of the program is a `BadIp` error.
+    pub fn execute_from(
+        &self,
+        tape: Vec<u8>,
+        ip: usize,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+    ) -> Result<ExecStats, RuntimeError> {
+        if ip > self.ops.len() {
+            return Err(RuntimeErrorKind::BadIp { ip }.into());
+        }
+        let mut state = VmState {
+            data: tape,
+            ip,
+            ..VmState::with_size(0)
+        };
+        if state.data.is_empty() {
+            state.data.push(0);
+        }
+        self.run_config(&mut state, read, write, &Config::new(), &mut ())
+    }
+
+    fn run_config<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        config: &Config,
+        hook: &mut H,
+    ) -> Result<ExecStats, RuntimeError> {
+        let result = if config.output_chunk > 1 {
+            let write = RetryingWrite {
+                write,
+                retries: config.write_retries,
+            };
+            let out = RefCell::new(BufWriter::with_capacity(config.output_chunk, write));
+            let mut read = FlushingRead { read, out: &out };
+            let result = self.run_input(state, &mut read, &mut SharedWrite(&out), config, hook);
+            let flushed = out.borrow_mut().flush().map_err(io_err);
+            result.and_then(|stats| flushed.map(|_| stats))
+        } else {
+            self.run_input(state, read, write, config, hook)
+        };
+        result.map_err(|mut err| {
+            if config.snapshot_on_error {
+                err.snapshot = Some(TapeSnapshot::capture(state, &err.kind));
+            }
+            err
+        })
+    }
+
+    /// Reads `config.input_chunk` bytes at a time. The buffer wraps the
+    /// `FlushingRead` of `run_config`, so output is only flushed when the
+    /// buffer runs out and reads from it.
+    fn run_input<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        config: &Config,
+        hook: &mut H,
+    ) -> Result<ExecStats, RuntimeError> {
+        if config.input_chunk > 1 {
+            let mut read = BufReader::with_capacity(config.input_chunk, read);
+            self.run_translated(state, &mut read, write, config, hook)
+        } else {
+            self.run_translated(state, read, write, config, hook)
+        }
+    }
+
+    fn run_translated<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        config: &Config,
+        hook: &mut H,
+    ) -> Result<ExecStats, RuntimeError> {
+        if config.input_translation.is_empty() {
+            self.run_limited(state, read, write, config, hook)
+        } else {
+            let mut read = TranslateInput::new(read, config.input_translation.clone());
+            self.run_limited(state, &mut read, write, config, hook)
+        }
+    }
+
+    fn run_limited<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        config: &Config,
+        hook: &mut H,
+    ) -> Result<ExecStats, RuntimeError> {
+        match config.output_preview {
+            Some(remaining) => {
+                let mut hook = (
+                    PreviewHook {
+                        remaining,
+                        limit: remaining,
+                    },
+                    hook,
+                );
+                self.run_steps(state, read, write, config, &mut hook)
+            }
+            None => self.run_steps(state, read, write, config, hook),
+        }
+    }
+
+    fn run_steps<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        config: &Config,
+        hook: &mut H,
+    ) -> Result<ExecStats, RuntimeError> {
+        match config.max_steps {
+            Some(max_steps) => {
+                let mut hook = (LimitHook { max_steps }, hook);
+                self.run_with(state, read, write, &mut stderr(), &mut hook)
+            }
+            None => self.run_with(state, read, write, &mut stderr(), hook),
+        }
+    }
+
+    /// Runs on `input`, collecting the output and the final tape.
+    pub fn run_capture(&self, input: &[u8]) -> Result<RunResult, RuntimeError> {
+        let mut state = VmState::new();
+        let mut output = vec![];
+        let stats = self.run(&mut state, &mut &input[..], &mut output, &mut stderr())?;
+        Ok(RunResult {
+            output,
+            tape: state.data,
+            stats,
+        })
+    }
+
+    /// Runs on `input`, recording it along with the output.
+    pub fn record_run(&self, input: &[u8]) -> Result<Recording, RuntimeError> {
+        Ok(Recording {
+            input: input.to_vec(),
+            output: self.run_capture(input)?.output,
+        })
+    }
+
+    /// Runs on the input of `recording`, returning whether the output is
+    /// still the recorded one.
+    pub fn replay_run(&self, recording: &Recording) -> Result<bool, RuntimeError> {
+        Ok(self.run_capture(&recording.input)?.output == recording.output)
+    }
+
+    /// The output of a program that reads no input. The first `,` fails as
+    /// at the end of input.
+    pub fn eval(&self) -> Result<Vec<u8>, RuntimeError> {
+        self.run_capture(&[]).map(|result| result.output)
+    }
+
+    /// The number of bytes the program writes given `input`, without keeping
+    /// them.
+    pub fn output_len(&self, input: &[u8]) -> Result<u64, RuntimeError> {
+        let mut count = CountingWrite(0);
+        self.execute(&mut &input[..], &mut count)?;
+        Ok(count.0)
+    }
+
+    /// Like `execute`, but every `,` calls `on_input` for the next byte,
+    /// where `None` is the end of input.
+    pub fn execute_with_input_fn(
+        &self,
+        on_input: &mut dyn FnMut() -> Option<u8>,
+        write: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        self.execute_with(&mut FnInput(on_input), write)
+    }
+
+    /// Like `execute`, but `#` dumps are written to `debug`.
+    pub fn execute_debug(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        debug: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        self.run(&mut VmState::new(), read, write, debug)
+            .map(|_| ())
+    }
+
+    pub fn execute_timed(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+    ) -> Result<(ExecStats, Duration), RuntimeError> {
+        let start = Instant::now();
+        let stats = self.run(&mut VmState::new(), read, write, &mut stderr())?;
+        Ok((stats, start.elapsed()))
+    }
+
+    /// Executes a single op. Returns `false` once the program has finished,
+    /// in which case `state` is left unchanged.
+    pub fn step(
+        &self,
+        state: &mut VmState,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+    ) -> Result<bool, RuntimeError> {
+        if state.ip >= self.ops.len() {
+            return Ok(false);
+        }
+        let mut hook = StepHook { stepped: false };
+        self.run_with(state, read, write, &mut stderr(), &mut hook)?;
+        Ok(true)
+    }
+
+    /// Runs only the ops in `range` on `state`, starting at its first op and
+    /// stopping when the ops run out or a jump leaves the range, with
+    /// `state.ip()` where the sub-run stopped. A `range` that is reversed or
+    /// goes past the end of the program is a `BadRange` error.
+    pub fn execute_range(
+        &self,
+        range: Range<usize>,
+        state: &mut VmState,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+    ) -> Result<ExecStats, RuntimeError> {
+        if range.start > range.end || range.end > self.ops.len() {
+            let (start, end) = (range.start, range.end);
+            return Err(RuntimeErrorKind::BadRange { start, end }.into());
+        }
+        state.ip = range.start;
+        self.run_with(state, read, write, &mut stderr(), &mut RangeHook { range })
+    }
+
+    /// Steps until `data[idx]` differs from its value when called, like a
+    /// debugger watchpoint. Returns `false` if the program finished first.
+    ///
+    /// Panics if `idx` is outside the tape.
+    pub fn run_until_cell_change(
+        &self,
+        state: &mut VmState,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        idx: usize,
+    ) -> Result<bool, RuntimeError> {
+        let value = state.data[idx];
+        let mut hook = WatchHook { idx, value };
+        self.run_with(state, read, write, &mut stderr(), &mut hook)?;
+        Ok(state.data[idx] != value)
+    }
+
+    /// Runs until the output so far ends with `needle`, such as a prompt,
+    /// stopping right after the byte that completes it. Returns `false` if
+    /// the program finished first.
+    pub fn run_until_output(
+        &self,
+        state: &mut VmState,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        needle: &[u8],
+    ) -> Result<bool, RuntimeError> {
+        let mut hook = OutputHook {
+            needle,
+            tail: Vec::with_capacity(needle.len() + 1),
+            matched: false,
+            limit: usize::MAX,
+        };
+        self.run_with(state, read, write, &mut stderr(), &mut hook)?;
+        Ok(hook.matched)
+    }
+
+    /// Number of ops in the compiled program.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Notes kept with the program, such as a hash of its source for a
+    /// cache. Building fills in `ops` with the number of ops, and when built
+    /// from source also `source_len` with its length in bytes and `opt` with
+    /// the `OptLevel`.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
+    }
+
+    /// Runs the program once per input, each on a fresh tape, discarding the
+    /// output.
+    pub fn execute_repeated(&self, inputs: &[Vec<u8>]) -> Vec<Result<ExecStats, RuntimeError>> {
+        inputs
+            .iter()
+            .map(|input| {
+                self.run(
+                    &mut VmState::new(),
+                    &mut &input[..],
+                    &mut sink(),
+                    &mut stderr(),
+                )
+            })
+            .collect()
+    }
+
+    /// Runs until `cancel` is set. The flag is checked every `interval` ops,
+    /// trading responsiveness for overhead.
+    pub fn execute_cancellable(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        cancel: Arc<AtomicBool>,
+        interval: u64,
+    ) -> Result<(), RuntimeError> {
+        let mut hook = CancelHook {
+            cancel,
+            interval: interval.max(1),
+        };
+        self.run_with(&mut VmState::new(), read, write, &mut stderr(), &mut hook)
+            .map(|_| ())
+    }
+
+    /// Stops with a `Timeout` error once running for longer than `timeout`.
+    /// The clock is read every few thousand ops, so a read or write that
+    /// blocks is not interrupted.
+    pub fn execute_timeout(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        timeout: Duration,
+    ) -> Result<ExecStats, RuntimeError> {
+        let mut hook = TimeoutHook {
+            start: Instant::now(),
+            timeout,
+        };
+        self.run_with(&mut VmState::new(), read, write, &mut stderr(), &mut hook)
+    }
+
+    /// Runs with `config`, but with a `PointerMode::Clamp` pointer, and
+    /// returns every attempt to reach off the tape with the result.
+    pub fn execute_clamped(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        config: &Config,
+    ) -> (Result<ExecStats, RuntimeError>, Vec<OverflowEvent>) {
+        let config = config.clone().pointer_mode(PointerMode::Clamp);
+        let mut state = VmState::with_config(&config);
+        let mut hook = OverflowHook { events: vec![] };
+        let result = self.run_config(&mut state, read, write, &config, &mut hook);
+        (result, hook.events)
+    }
+
+    /// Runs with `config`, but with a `PointerMode::Grow` tape, calling
+    /// `on_grow` with the tape's length each time it grows.
+    pub fn execute_growing(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        config: &Config,
+        on_grow: &mut dyn FnMut(usize),
+    ) -> Result<ExecStats, RuntimeError> {
+        let config = config.clone().pointer_mode(PointerMode::Grow);
+        let mut state = VmState::with_config(&config);
+        let mut hook = GrowHook {
+            len: state.data.len(),
+            on_grow,
+        };
+        let result = self.run_config(&mut state, read, write, &config, &mut hook);
+        // The last op may have grown the tape too.
+        hook.check(&state.data);
+        result
+    }
+
+    /// Stops with a `StepLimit` error instead of executing more than
+    /// `max_steps` ops.
+    pub fn execute_limited(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        max_steps: u64,
+    ) -> Result<ExecStats, RuntimeError> {
+        self.execute_with_config(read, write, &Config::new().max_steps(max_steps))
+    }
+
+    /// Runs with a budget of `gas`, charging every op its cost in `schedule`,
+    /// and returns the gas left over.
+    pub fn execute_gas(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        gas: u64,
+        schedule: &GasSchedule,
+    ) -> Result<u64, RuntimeError> {
+        let mut hook = GasHook { gas, schedule };
+        self.run_with(&mut VmState::new(), read, write, &mut stderr(), &mut hook)?;
+        Ok(hook.gas)
+    }
+
+    /// Whether the program finishes on `input` within `max_steps` ops. Errors
+    /// other than reaching the limit are still returned.
+    pub fn will_halt_within(&self, input: &[u8], max_steps: u64) -> Result<bool, RuntimeError> {
+        match self.execute_limited(&mut &input[..], &mut sink(), max_steps) {
+            Ok(_) => Ok(true),
+            Err(err) if matches!(err.kind, RuntimeErrorKind::StepLimit { .. }) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Runs the program, collecting a `Profile`. Scanning the final tape for
+    /// the value histogram is only done when `histogram` is set.
+    pub fn execute_profiled(
+        &self,
+        read: &mut dyn Read,
+        write: &mut dyn Write,
+        histogram: bool,
+    ) -> Result<Profile, RuntimeError> {
+        let mut state = VmState::new();
+        let mut hook = ProfileHook {
+            touched: vec![false; TAPE_SIZE],
+            written: vec![false; TAPE_SIZE],
+            loop_iterations: BTreeMap::new(),
+        };
+        let stats = self.run_with(&mut state, read, write, &mut stderr(), &mut hook)?;
+        let histogram = histogram.then(|| {
+            let mut histogram = BTreeMap::new();
+            for &c in state.data.iter().filter(|&&c| c != 0) {
+                *histogram.entry(c).or_insert(0) += 1;
+            }
+            histogram
+        });
+        Ok(Profile {
+            stats,
+            distinct_cells: hook.touched.iter().filter(|&&t| t).count(),
+            written_cells: (0..TAPE_SIZE).filter(|&idx| hook.written[idx]).collect(),
+            loop_iterations: hook.loop_iterations,
+            histogram,
+        })
+    }
+
+    pub(crate) fn run<R: Read + ?Sized, W: Write + ?Sized>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        debug: &mut dyn Write,
+    ) -> Result<ExecStats, RuntimeError> {
+        self.run_with(state, read, write, debug, &mut ())
+    }
+
+    fn run_with<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        debug: &mut dyn Write,
+        hook: &mut H,
+    ) -> Result<ExecStats, RuntimeError> {
+        let mut stats = ExecStats::default();
+        let result = self.run_ops(state, read, write, debug, hook, &mut stats);
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(()) => log::trace!("ran {} ops in {} steps", self.ops.len(), stats.steps),
+            Err(err) => log::trace!(
+                "ran {} ops in {} steps, then: {}",
+                self.ops.len(),
+                stats.steps,
+                err
+            ),
+        }
+        result.map(|()| stats).map_err(|err| RuntimeError {
+            ip: Some(state.ip),
+            ..err
+        })
+    }
+
+    fn run_ops<R: Read + ?Sized, W: Write + ?Sized, H: Hook>(
+        &self,
+        state: &mut VmState,
+        read: &mut R,
+        write: &mut W,
+        debug: &mut dyn Write,
+        hook: &mut H,
+        stats: &mut ExecStats,
+    ) -> Result<(), RuntimeError> {
+        let data = &mut state.data;
+        let (pointer_mode, max_tape, eof, cells, output_format) = (
+            state.pointer_mode,
+            state.max_tape,
+            state.eof,
+            (state.cell_mode, state.cell_overflow),
+            state.output_format,
+        );
+
+        while let Some(&op) = self.ops.get(state.ip) {
+            if !hook.before(state.ip, op, state.ptr, data, stats)? {
+                break;
+            }
+            stats.steps += 1;
+            match op {
+                Move { d } => {
+                    state.ptr = match on_tape(state.ptr, d, data.len()) {
+                        Some(idx) => idx,
+                        None => off_tape(state.ptr, d, data, pointer_mode, max_tape)?,
+                    };
+                }
+                Add { d } => add(data, state.ptr, d, cells)?,
+                AddAt { off, d } => {
+                    let idx = match on_tape(state.ptr, off, data.len()) {
+                        Some(idx) => idx,
+                        None => off_tape_at(state.ptr, off, data, pointer_mode, max_tape)?,
+                    };
+                    add(data, idx, d, cells)?;
+                }
+                Scan { d } => {
+                    while data[state.ptr] != 0 {
+                        state.ptr = match on_tape(state.ptr, d, data.len()) {
+                            Some(idx) => idx,
+                            None => off_tape(state.ptr, d, data, pointer_mode, max_tape)?,
+                        };
+                        // Each further stride is a step, so limits and hooks
+                        // see a scan that never finds a zero.
+                        if data[state.ptr] != 0 {
+                            if !hook.before(state.ip, op, state.ptr, data, stats)? {
+                                return Ok(());
+                            }
+                            stats.steps += 1;
+                        }
+                    }
+                }
+                MoveAdd { off } => {
+                    let value = data[state.ptr];
+                    if value != 0 {
+                        let idx = match on_tape(state.ptr, off, data.len()) {
+                            Some(idx) => idx,
+                            None => off_tape_at(state.ptr, off, data, pointer_mode, max_tape)?,
+                        };
+                        add(data, idx, value as isize, cells)?;
+                        data[state.ptr] = 0;
+                    }
+                }
+                MulAdd { off, factor } => {
+                    let value = data[state.ptr];
+                    if value != 0 {
+                        let idx = match on_tape(state.ptr, off, data.len()) {
+                            Some(idx) => idx,
+                            None => off_tape_at(state.ptr, off, data, pointer_mode, max_tape)?,
+                        };
+                        add(data, idx, (value as isize).wrapping_mul(factor), cells)?;
+                    }
+                }
+                Out => write_out(write, data[state.ptr], output_format, state.write_retries)?,
+                OutRange { off, pre, step } => {
+                    let count = data[state.ptr];
+                    if count != 0 {
+                        let idx = match on_tape(state.ptr, off, data.len()) {
+                            Some(idx) => idx,
+                            None => off_tape_at(state.ptr, off, data, pointer_mode, max_tape)?,
+                        };
+                        let (pre, post) = (pre as isize, step as isize - pre as isize);
+                        let n = (count as usize).min(hook.output_limit()) as u8;
+                        for _ in 0..n {
+                            add(data, idx, pre, cells)?;
+                            write_out(write, data[idx], output_format, state.write_retries)?;
+                            add(data, idx, post, cells)?;
+                        }
+                        data[state.ptr] = count - n;
+                        // Like a loop paused between iterations.
+                        if n < count {
+                            continue;
+                        }
+                    }
+                }
+                In => {
+                    let mut byte = [0];
+                    match read.read_exact(&mut byte) {
+                        Ok(()) => data[state.ptr] = byte[0],
+                        Err(err)
+                            if matches!(
+                                err.kind(),
+                                ErrorKind::UnexpectedEof | ErrorKind::WouldBlock
+                            ) && eof != EofMode::Error =>
+                        {
+                            if eof == EofMode::Zero {
+                                data[state.ptr] = 0;
+                            }
+                        }
+                        Err(err) => return Err(io_err(err)),
+                    }
+                }
+                JmpZ { addr } => {
+                    if data[state.ptr] == 0 {
+                        state.ip = addr;
+                        continue;
+                    }
+                }
+                // The back-edge, taken on every iteration but the last.
+                JmpNz { addr } => {
+                    if data[state.ptr] != 0 {
+                        state.ip = addr;
+                        continue;
+                    }
+                }
+                Halt => {
+                    state.ip = self.ops.len();
+                    return Ok(());
+                }
+                Dump => {
+                    let window = window(state.ptr, data.len());
+                    writeln!(
+                        debug,
+                        "ptr = {}, data[{}..{}] = {:?}",
+                        state.ptr,
+                        window.start,
+                        window.end,
+                        &data[window.clone()]
+                    )
+                    .map_err(io_err)?;
+                }
+                PrintPtr => writeln!(debug, "ptr = {}", state.ptr).map_err(io_err)?,
+                Clear => data[state.ptr] = 0,
+                SwapAcc => std::mem::swap(&mut data[state.ptr], &mut state.acc),
+                ClearRange { start_off, len } => {
+                    let cells = cleared_cells(state.ptr as isize, start_off, len);
+                    let (start, end) = (cells.start, cells.end);
+                    let size = data.len() as isize;
+                    if start >= 0 && end <= size {
+                        data[start as usize..end as usize].fill(0);
+                    } else if pointer_mode == PointerMode::Wrap {
+                        for idx in cells {
+                            data[idx.rem_euclid(size) as usize] = 0;
+                        }
+                    } else {
+                        data[on_tape_part(cells, size as usize)].fill(0);
+                        // Cells past the end of a growing tape are already 0.
+                        let limit = match pointer_mode {
+                            PointerMode::Grow => max_tape as isize,
+                            _ => size,
+                        };
+                        if start < 0 || end > limit {
+                            let idx = if start < 0 { -1 } else { limit };
+                            return Err(RuntimeErrorKind::DataOverflow { idx }.into());
+                        }
+                    }
+                }
+            }
+
+            state.ip += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for Interpreter {
+    type Error = BuildError;
+
+    fn try_from(code: &str) -> Result<Self, Self::Error> {
+        Self::build(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::reference::assert_same_behavior;
+
+    struct MockInOut {
+        data: VecDeque<u8>,
+        bad: bool,
+    }
+
+    impl MockInOut {
+        fn new(d: Vec<u8>) -> Self {
+            let data = VecDeque::from(d);
+            Self { data, bad: false }
+        }
+
+        fn dummy() -> Self {
+            Self {
+                data: VecDeque::new(),
+                bad: false,
+            }
+        }
+
+        fn bad() -> Self {
+            Self {
+                data: VecDeque::new(),
+                bad: true,
+            }
+        }
+    }
+
+    impl Read for MockInOut {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.bad {
+                return Err(std::io::Error::other("read"));
+            }
+
+            let mut cnt = 0usize;
+            for b in buf.iter_mut() {
+                if let Some(c) = self.data.pop_front() {
+                    *b = c;
+                    cnt += 1;
+                } else {
+                    break;
+                }
+            }
+            Ok(cnt)
+        }
+    }
+
+    impl Write for MockInOut {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.bad {
+                return Err(std::io::Error::other("write"));
+            }
+
+            for c in buf {
+                self.data.push_back(*c);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_basic() {
+        let code = "<+>-.,[]";
+        let ops = Interpreter::parse(code.as_bytes(), &BuildOptions::default()).unwrap();
+
+        let expected = [
+            Move { d: -1 },
+            Add { d: 1 },
+            Move { d: 1 },
+            Add { d: -1 },
+            Out,
+            In,
+            JmpZ { addr: 8 },
+            JmpNz { addr: 7 },
+        ];
+
+        assert_eq!(expected.len(), ops.len());
+        for (idx, op) in ops.iter().enumerate() {
+            assert_eq!(expected[idx], *op);
+        }
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let code = "+\n\t+\t]";
+        let err = Interpreter::build(code).unwrap_err();
+        assert_eq!((2, 4), (err.line(), err.col()));
+
+        let options = BuildOptions {
+            tab_width: 4,
+            ..Default::default()
+        };
+        let err = Interpreter::build_with(code, &options).unwrap_err();
+        assert_eq!((2, 9), (err.line(), err.col()));
+        let err = Interpreter::build_with("\t  \t[", &options).unwrap_err();
+        assert_eq!((1, 9), (err.line(), err.col()));
+
+        let mut builder = IncrementalBuilder::new(options);
+        builder.rebuild("[\t+]", 0).unwrap();
+        let err = builder.rebuild("[\t+]\t]", 5).unwrap_err();
+        assert_eq!((1, 9), (err.line(), err.col()));
+    }
+
+    #[test]
+    fn test_build_bytes() {
+        let code = b"+++\xff comment\n\xfe>++[<+>-]<.]";
+        let err = Interpreter::build_bytes(code).unwrap_err();
+        assert_eq!("bracket not match, line = 2, col = 13", err.to_string());
+
+        let inter = Interpreter::build_bytes(&code[..code.len() - 1]).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![5], out.data.iter().copied().collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        assert_eq!("+", canonicalize("++--+").unwrap());
+        assert_eq!(
+            canonicalize("++[->+<]>.").unwrap(),
+            canonicalize("+ add +\n[-<>> copy +<] ><>.").unwrap()
+        );
+        assert_eq!("-", canonicalize(&"+".repeat(255)).unwrap());
+        assert!(canonicalize("[").is_err());
+    }
+
+    #[test]
+    fn test_minify() {
+        let code = format!("[-]{}.", "+".repeat(250));
+        assert_eq!("[-]------.", minify(&code).unwrap());
+        // The loop cannot run on the cleared cell.
+        assert_eq!("[-]>.", minify("[-] clear [>.<-] >.").unwrap());
+        let code = "++++++++[>++++++++<-]>+.";
+        assert_eq!(code, minify(code).unwrap());
+        assert!(minify("]").is_err());
+    }
+
+    #[test]
+    fn test_try_from() {
+        let inter: Interpreter = "++[>+<-]".try_into().unwrap();
+        assert_eq!(Interpreter::build("++[>+<-]").unwrap().ops, inter.ops);
+        let result: Result<Interpreter, _> = "+]".try_into();
+        assert_eq!(
+            "bracket not match, line = 1, col = 2",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_opt_level() {
+        let code = "++ +[-]>>";
+        let inter = Interpreter::build_opt(code, OptLevel::O0).unwrap();
+        assert_eq!(
+            vec![
+                Add { d: 1 },
+                Add { d: 1 },
+                Add { d: 1 },
+                JmpZ { addr: 6 },
+                Add { d: -1 },
+                JmpNz { addr: 4 },
+                Move { d: 1 },
+                Move { d: 1 }
+            ],
+            inter.ops
+        );
+        let inter = Interpreter::build_opt(code, OptLevel::O1).unwrap();
+        assert_eq!(
+            vec![
+                Add { d: 3 },
+                JmpZ { addr: 4 },
+                Add { d: -1 },
+                JmpNz { addr: 2 },
+                Move { d: 2 }
+            ],
+            inter.ops
+        );
+        assert_eq!(inter.ops, Interpreter::build(code).unwrap().ops);
+        let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+        assert_eq!(vec![Add { d: 3 }, Clear, Move { d: 2 }], inter.ops);
+
+        // Folding runs does not move error positions.
+        for opt in [OptLevel::O0, OptLevel::O1] {
+            let err = Interpreter::build_opt("+->><]", opt).unwrap_err();
+            assert_eq!("bracket not match, line = 1, col = 6", err.to_string());
+        }
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log() {
+        use std::sync::Mutex;
+
+        struct TestLogger(Mutex<Vec<String>>);
+
+        impl log::Log for TestLogger {
+            fn enabled(&self, metadata: &log::Metadata) -> bool {
+                metadata.level() <= log::Level::Trace
+            }
+
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: TestLogger = TestLogger(Mutex::new(vec![]));
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let code = "+".repeat(1000) + ".";
+        let inter = Interpreter::build_opt(&code, OptLevel::O2).unwrap();
+        inter.execute(&mut std::io::empty(), &mut vec![]).unwrap();
+        let records = LOGGER.0.lock().unwrap();
+        for record in [
+            "building 1001 bytes at O2",
+            "built 2 ops from 1001 bytes at O2",
+            "ran 2 ops in 2 steps",
+        ] {
+            assert!(records.iter().any(|r| r == record), "{}", record);
+        }
+    }
+
+    #[test]
+    fn test_build_stats() {
+        let code = "++--+-+ cancelled >><<<> +-[ -]>+>+<<";
+        let options = BuildOptions {
+            opt: OptLevel::O2,
+            ..Default::default()
+        };
+        let (inter, stats) = Interpreter::build_with_stats(code, &options).unwrap();
+        assert_eq!(
+            BuildStats {
+                commands: 24,
+                folded: 9,
+                loops_removed: 2,
+                moves_removed: 3,
+                ops: 4,
+            },
+            stats
+        );
+        assert_eq!(stats.ops, inter.len());
+
+        let (_, stats) = Interpreter::build_with_stats(code, &BuildOptions::default()).unwrap();
+        assert_eq!(9, stats.folded);
+        assert_eq!(9, stats.ops);
+    }
+
+    #[test]
+    fn test_fold() {
+        let code = "<><<>><+-++--+<>+-";
+        let interpreter = Interpreter::build(code).unwrap();
+
+        let expected = [Move { d: -1 }, Add { d: 1 }];
+
+        assert_eq!(expected.len(), interpreter.ops.len());
+        for (idx, op) in interpreter.ops.iter().enumerate() {
+            assert_eq!(expected[idx], *op);
+        }
+    }
+
+    #[test]
+    fn test_clear() {
+        let inter = Interpreter::build_opt("+++[-]>>[+]", OptLevel::O2).unwrap();
+        assert_eq!(vec![Add { d: 3 }, Clear, Move { d: 2 }, Clear], inter.ops);
+
+        let inter = Interpreter::build_opt("[-]>[-]>[-]>", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                ClearRange {
+                    start_off: 0,
+                    len: 3
+                },
+                Move { d: 3 }
+            ],
+            inter.ops
+        );
+
+        let inter = Interpreter::build_opt("[-]<[-]<[-]", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                ClearRange {
+                    start_off: -2,
+                    len: 3
+                },
+                Move { d: -2 }
+            ],
+            inter.ops
+        );
+
+        let inter = Interpreter::build_opt(">>[-]>[-]>[-]<<<[-]>", OptLevel::O2).unwrap();
+        let mut state = VmState::new();
+        state.data.fill(7);
+        inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+            )
+            .unwrap();
+        assert_eq!([7, 0, 0, 0, 0, 7], state.data[..6]);
+
+        let inter = Interpreter::build_opt(">[-]<[-]<[-]", OptLevel::O2).unwrap();
+        let mut state = VmState::new();
+        state.data.fill(7);
+        let err = inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+            )
+            .unwrap_err();
+        assert_eq!("data overflow, idx = -1", err.to_string());
+        assert_eq!([0, 0, 7], state.data[..3]);
+
+        // Ranges lying wholly off the tape.
+        for (d, start_off, idx) in [(9, 2, 10), (0, -5, -1)] {
+            let ops = [Move { d }, ClearRange { start_off, len: 2 }];
+            let inter = Interpreter::from_bytecode(&bytecode::encode(&ops)).unwrap();
+            let err = inter
+                .execute_with_config(
+                    &mut std::io::empty(),
+                    &mut sink(),
+                    &Config::new().tape_size(10),
+                )
+                .unwrap_err();
+            assert_eq!(&RuntimeErrorKind::DataOverflow { idx }, err.kind());
+        }
+
+        let mut hook = ProfileHook {
+            touched: vec![false; 4],
+            written: vec![false; 4],
+            loop_iterations: BTreeMap::new(),
+        };
+        let op = ClearRange {
+            start_off: -2,
+            len: 3,
+        };
+        hook.before(0, op, 0, &[1; 4], &ExecStats::default())
+            .unwrap();
+        assert_eq!(vec![true, false, false, false], hook.written);
+        hook.before(0, op, 3, &[1; 4], &ExecStats::default())
+            .unwrap();
+        assert_eq!(vec![true, true, true, true], hook.touched);
+    }
+
+    #[test]
+    fn test_scan() {
+        let code = "+>+>+>+>+<<<<[>>]+++.";
+        let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+        assert!(inter.ops.contains(&Scan { d: 2 }));
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![3], out.data.iter().copied().collect::<Vec<u8>>());
+
+        let inter = Interpreter::build_opt(">>+[<<<]", OptLevel::O2).unwrap();
+        let err = inter
+            .execute(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+            .unwrap_err();
+        assert_eq!("data overflow, idx = -1", err.to_string());
+
+        let err = inter
+            .execute_profiled(&mut MockInOut::dummy(), &mut MockInOut::dummy(), false)
+            .unwrap_err();
+        assert_eq!("data overflow, idx = -1", err.to_string());
+
+        let inter = Interpreter::build_opt("+>>+>>+<<<<[>>]", OptLevel::O2).unwrap();
+        let profile = inter
+            .execute_profiled(&mut MockInOut::dummy(), &mut MockInOut::dummy(), false)
+            .unwrap();
+        assert_eq!(4, profile.distinct_cells);
+
+        // A wrapping tape with no zero cell, where the scan never ends.
+        let options = BuildOptions {
+            opt: OptLevel::O2,
+            pointer_mode: PointerMode::Wrap,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with("+>+>+>+>[>]", &options).unwrap();
+        assert!(inter.ops.contains(&Scan { d: 1 }));
+        let config = Config::new()
+            .tape_size(4)
+            .pointer_mode(PointerMode::Wrap)
+            .max_steps(1000);
+        let err = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut vec![], &config)
+            .unwrap_err();
+        assert_eq!(&RuntimeErrorKind::StepLimit { steps: 1000 }, err.kind());
+    }
+
+    #[test]
+    fn test_move_add() {
+        let inter = Interpreter::build_opt("[->+<]", OptLevel::O2).unwrap();
+        assert_eq!(vec![MoveAdd { off: 1 }], inter.ops);
+        let inter = Interpreter::build_opt("[-<<+>>]", OptLevel::O2).unwrap();
+        assert_eq!(vec![MoveAdd { off: -2 }], inter.ops);
+        let inter = Interpreter::build_opt("[>+<-]", OptLevel::O2).unwrap();
+        assert_eq!(vec![MoveAdd { off: 1 }], inter.ops);
+        let inter = Interpreter::build_opt("[->+>]", OptLevel::O2).unwrap();
+        assert!(!inter.ops.contains(&MoveAdd { off: 1 }));
+
+        let inter = Interpreter::build_opt("+++>+++++[-<+>]<.", OptLevel::O2).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![8], out.data.iter().copied().collect::<Vec<u8>>());
+
+        let inter = Interpreter::build_opt(">>+++<<++[->>+<<]>>.", OptLevel::O2).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![5], out.data.iter().copied().collect::<Vec<u8>>());
+
+        let inter = Interpreter::build_opt("[-<+>]+[-<+>]", OptLevel::O2).unwrap();
+        let err = inter
+            .execute(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+            .unwrap_err();
+        assert_eq!("data overflow, idx = -1", err.to_string());
+    }
+
+    #[test]
+    fn test_mul_add() {
+        let inter = Interpreter::build_opt("[-<->]", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                MulAdd {
+                    off: -1,
+                    factor: -1
+                },
+                Clear
+            ],
+            inter.ops
+        );
+        let inter = Interpreter::build_opt("[>+++>-<<-]", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                MulAdd { off: 1, factor: 3 },
+                MulAdd { off: 2, factor: -1 },
+                Clear
+            ],
+            inter.ops
+        );
+        for code in ["[->+<<]", "[-->+<]", "[->+<.]"] {
+            let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+            assert!(matches!(inter.ops[0], JmpZ { .. }));
+        }
+
+        let inter = Interpreter::build_opt("++++++++>+++[-<->]<.", OptLevel::O2).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![5], out.data.iter().copied().collect::<Vec<u8>>());
+
+        let inter = Interpreter::build_opt("+++[->--<]>.", OptLevel::O2).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![250], out.data.iter().copied().collect::<Vec<u8>>());
+
+        // On a wrapping tape of 1 cell, `>+` adds back to the counter.
+        let config = Config::new()
+            .tape_size(1)
+            .pointer_mode(PointerMode::Wrap)
+            .max_steps(1000);
+        for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let options = BuildOptions {
+                opt,
+                pointer_mode: PointerMode::Wrap,
+                ..Default::default()
+            };
+            let inter = Interpreter::build_with("+[->+<].", &options).unwrap();
+            let err = inter
+                .execute_with_config(&mut MockInOut::dummy(), &mut vec![], &config)
+                .unwrap_err();
+            assert!(
+                matches!(err.kind(), RuntimeErrorKind::StepLimit { .. }),
+                "{:?}",
+                opt
+            );
+        }
+    }
+
+    #[test]
+    fn test_counted_loops() {
+        let inter = Interpreter::build_opt(",[-]++++++[-->+++<]>.", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                In,
+                Clear,
+                Add { d: 6 },
+                AddAt { off: 1, d: 9 },
+                Clear,
+                Move { d: 1 },
+                Out
+            ],
+            inter.ops
+        );
+        let inter = Interpreter::build_opt(",[-][.,]>[-]+++[-->+<]", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![In, Clear, Move { d: 1 }, Clear, Add { d: 3 }],
+            inter.ops[..5]
+        );
+        assert!(matches!(inter.ops[5], JmpZ { .. }));
+        // The cells a program starts with can be set at runtime.
+        let inter = Interpreter::build_opt("[-->+<]", OptLevel::O2).unwrap();
+        assert!(matches!(inter.ops[0], JmpZ { .. }));
+
+        let inter = Interpreter::build_opt("[-]++++++[-->+++<]>.", OptLevel::O2).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(vec![9], out.data.iter().copied().collect::<Vec<u8>>());
+
+        // On a wrapping tape of 2 cells, `>>+` adds to the cleared cell.
+        let config = Config::new().tape_size(2).pointer_mode(PointerMode::Wrap);
+        for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let options = BuildOptions {
+                opt,
+                pointer_mode: PointerMode::Wrap,
+                ..Default::default()
+            };
+            let inter = Interpreter::build_with("[-]>>+<<[.-]", &options).unwrap();
+            let mut out = vec![];
+            inter
+                .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+                .unwrap();
+            assert_eq!(vec![1], out, "{:?}", opt);
+        }
+    }
+
+    #[test]
+    fn test_out_range() {
+        let code = "++++++++[>++++++++<-]>+<+++++[>.+<-]>.";
+        let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+        assert!(inter.ops.contains(&OutRange {
+            off: 1,
+            pre: 0,
+            step: 1
+        }));
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(
+            b"ABCDEF".to_vec(),
+            out.data.iter().copied().collect::<Vec<u8>>()
+        );
+        assert_eq!(Some(b"ABCDEF".to_vec()), inter.output_is_constant());
+        assert_eq!((false, true), inter.does_io());
+        assert_eq!(
+            inter.ops,
+            Interpreter::from_asm(&inter.to_asm()).unwrap().ops
+        );
+        assert_eq!(
+            inter.ops,
+            Interpreter::from_bytecode(&inter.to_bytecode())
+                .unwrap()
+                .ops
+        );
+
+        let inter = Interpreter::build_opt(",[>+.--<-]", OptLevel::O2).unwrap();
+        assert_eq!(
+            OutRange {
+                off: 1,
+                pre: 1,
+                step: -1
+            },
+            inter.ops[1]
+        );
+        let mut out = vec![];
+        inter.execute(&mut &[3][..], &mut out).unwrap();
+        assert_eq!(vec![1, 0, 255], out);
+
+        // On a wrapping tape of 1 cell, `+` adds back to the counter.
+        let config = Config::new()
+            .tape_size(1)
+            .pointer_mode(PointerMode::Wrap)
+            .max_steps(1000);
+        let options = BuildOptions {
+            opt: OptLevel::O2,
+            pointer_mode: PointerMode::Wrap,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with("+[>.+<-]", &options).unwrap();
+        let err = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut vec![], &config)
+            .unwrap_err();
+        assert!(matches!(err.kind(), RuntimeErrorKind::StepLimit { .. }));
+
+        // Running the passes again must not take the printed cell as known.
+        let mut inter = Interpreter::build_opt(">[-]<[-]+++[>.+<-]>[.-]", OptLevel::O2).unwrap();
+        let mut before = vec![];
+        inter.execute(&mut std::io::empty(), &mut before).unwrap();
+        assert_eq!(vec![0, 1, 2, 3, 2, 1], before);
+        inter.optimize();
+        let mut after = vec![];
+        inter.execute(&mut std::io::empty(), &mut after).unwrap();
+        assert_eq!(before, after);
+
+        // The printed cell has to change by the same amount every time.
+        for code in [
+            "[.-]",
+            "[>.+<--]",
+            "[>.+>+<<-]",
+            "[>.<.-]",
+            "[>.,<-]",
+            "[>.+<-<]",
+        ] {
+            let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+            assert!(matches!(inter.ops[0], JmpZ { .. }), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_sink_moves() {
+        let inter = Interpreter::build_opt(">+>++<<-", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                AddAt { off: 1, d: 1 },
+                AddAt { off: 2, d: 2 },
+                Add { d: -1 }
+            ],
+            inter.ops
+        );
+
+        let code = include_str!("../brainfuck/helloworld.bf");
+        let raw = Interpreter::from_ops(
+            Interpreter::parse(code.as_bytes(), &BuildOptions::default()).unwrap(),
+        );
+        let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+        let moves = |inter: &Interpreter| {
+            inter
+                .ops
+                .iter()
+                .filter(|op| matches!(op, Move { .. }))
+                .count()
+        };
+        assert!(inter.ops.len() < raw.ops.len());
+        assert!(moves(&inter) < moves(&raw));
+
+        let mut expected = MockInOut::dummy();
+        raw.execute(&mut MockInOut::dummy(), &mut expected).unwrap();
+        let mut out = MockInOut::dummy();
+        inter.execute(&mut MockInOut::dummy(), &mut out).unwrap();
+        assert_eq!(expected.data, out.data);
+
+        let inter = Interpreter::build_opt(">+<<+", OptLevel::O2).unwrap();
+        let err = inter
+            .execute(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+            .unwrap_err();
+        assert_eq!("data overflow, idx = -1", err.to_string());
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let inter = Interpreter::build_opt(",[>[-]<[->+<]>.]", OptLevel::O2).unwrap();
+        let expected = [
+            "    0  in",
+            "    1  jz 9",
+            "    2  move 1",
+            "    3  clear",
+            "    4  move -1",
+            "    5  move_add 1",
+            "    6  move 1",
+            "    7  out",
+            "    8  jnz 2",
+        ];
+        assert_eq!(expected.join("\n") + "\n", inter.disassemble());
+    }
+
+    #[test]
+    fn test_asm() {
+        let inter = Interpreter::build_opt(",[>[-]<[->+<]>.]", OptLevel::O2).unwrap();
+        let text = inter.to_asm();
+        assert_eq!(
+            "in\njz L1\n    move 1\n    clear\n    move -1\n    move_add 1\n    move 1\n    out\njnz L1\n",
+            text
+        );
+        let parsed = Interpreter::from_asm(&text).unwrap();
+        assert_eq!(inter.ops, parsed.ops);
+
+        let inter = Interpreter::from_asm("add 65\nclear 1..3 ; unused\nout").unwrap();
+        assert_eq!(b"A".to_vec(), inter.eval().unwrap());
+        assert_eq!(
+            "invalid asm at line 1",
+            Interpreter::from_asm("jz x").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_instruction_table() {
+        let inter = Interpreter::build_opt(",[>[-]<[->+<]>.]", OptLevel::O2).unwrap();
+        assert_eq!(
+            vec![
+                (3, 0),
+                (4, 9),
+                (0, 1),
+                (8, 0),
+                (0, -1),
+                (12, 1),
+                (0, 1),
+                (2, 0),
+                (5, 2)
+            ],
+            inter.instruction_table()
+        );
+
+        let inter = Interpreter::build_opt(",[-<++>>---<]>>[-]>[-]", OptLevel::O2).unwrap();
+        let table = inter.instruction_table();
+        assert_eq!((14, -1 << 32 | 2), table[1]);
+        assert_eq!((14, 1 << 32 | (-3i32 as u32 as i64)), table[2]);
+        assert_eq!((9, 2), table[5]);
+    }
+
+    #[test]
+    fn test_reproduction_up_to() {
+        let inter = Interpreter::build("++>+.<<+.>>>.").unwrap();
+        let err = inter.eval().unwrap_err();
+        assert_eq!(Some(4), err.ip());
+        let repro = inter.reproduction_up_to(err.ip().unwrap());
+        assert_eq!("++>+.<<", repro);
+        let err = Interpreter::build(&repro).unwrap().eval().unwrap_err();
+        assert_eq!(&RuntimeErrorKind::DataOverflow { idx: -1 }, err.kind());
+
+        let inter = Interpreter::build("+[>+++[-<<]]").unwrap();
+        let err = inter.eval().unwrap_err();
+        assert_eq!("+[>+++[-<<]]", inter.reproduction_up_to(err.ip().unwrap()));
+        assert_eq!("+[>]", inter.reproduction_up_to(2));
+        // An ip past the end keeps the whole program.
+        assert_eq!("+[>+++[-<<]]", inter.reproduction_up_to(100));
+        assert_eq!("+[>+++[-<<]]", inter.reproduction_up_to(usize::MAX));
+    }
+
+    #[test]
+    fn test_to_source() {
+        let code = ",>,<[->>+++<<]>>>[-]>[-]<<[-<+>]<<.>.>[<]+++>+.<<[->+>++<<]>.>.+>++>+++<<.";
+        let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+        let source = to_source(&inter.ops);
+        assert!(inter.ops.iter().any(|op| matches!(op, AddAt { .. })));
+        for input in [[3u8, 4], [0, 9], [200, 1]] {
+            let run = |code: &str| {
+                Interpreter::build(code)
+                    .unwrap()
+                    .run_capture(&input)
+                    .unwrap()
+                    .output
+            };
+            assert_eq!(run(code), run(&source));
+        }
+    }
+
+    #[test]
+    fn test_may_both_be_nonzero() {
+        let inter = Interpreter::build(",[>+<-]>[-]>,").unwrap();
+        assert!(!inter.may_both_be_nonzero(0, 2));
+        assert!(!inter.may_both_be_nonzero(1, 2));
+        assert!(inter.may_both_be_nonzero(0, 1));
+        assert!(!inter.may_both_be_nonzero(0, 5));
+
+        let inter = Interpreter::build_opt(",[>+<-]>[-]>,", OptLevel::O2).unwrap();
+        assert!(!inter.may_both_be_nonzero(0, 2));
+        assert!(inter.may_both_be_nonzero(0, 1));
+
+        let inter = Interpreter::build(",[-]>,[>]").unwrap();
+        assert!(!inter.may_both_be_nonzero(0, 1));
+        // After `[>]` the write could be to any cell.
+        let inter = Interpreter::build(",[-]>[>]<,").unwrap();
+        assert!(inter.may_both_be_nonzero(0, 3));
+    }
+
+    #[test]
+    fn test_lint_drift() {
+        assert_eq!(
+            vec![LoopDrift {
+                line: 1,
+                col: 1,
+                drift: 1
+            }],
+            Interpreter::lint_drift("[>]").unwrap()
+        );
+        assert!(Interpreter::lint_drift("[->+<]").unwrap().is_empty());
+
+        let drifts = Interpreter::lint_drift("+[>[-]<\n  [<<+>]>]").unwrap();
+        assert_eq!(1, drifts.len());
+        assert_eq!(
+            "loop at line 2, col 3 moves the pointer by -1 each iteration",
+            drifts[0].to_string()
+        );
+        assert!(Interpreter::lint_drift("[>").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let options = BuildOptions {
+            opt: OptLevel::O0,
+            ..Default::default()
+        };
+        let ops = Interpreter::parse(&COMMANDS, &options).unwrap();
+        assert_eq!(COMMANDS.len(), ops.len());
+        for c in 0..=255u8 {
+            let code = [b'[', c, b']'];
+            let ops = Interpreter::parse(&code, &options);
+            assert_eq!(
+                COMMANDS.contains(&c),
+                ops.map_or(true, |ops| ops.len() == 3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_command_histogram() {
+        let histogram = Interpreter::command_histogram(include_str!("../brainfuck/helloworld.bf"));
+        assert_eq!(
+            BTreeMap::from([
+                ('+', 41),
+                (',', 8),
+                ('-', 22),
+                ('.', 19),
+                ('<', 9),
+                ('>', 19),
+                ('[', 6),
+                (']', 6)
+            ]),
+            histogram
+        );
+        assert_eq!(
+            BTreeMap::from([('+', 2), ('>', 1)]),
+            Interpreter::command_histogram("+a>+#")
+        );
+    }
+
+    #[test]
+    fn test_explain_range() {
+        assert_eq!(
+            "add 3, move right 1",
+            Interpreter::explain_range("+++>", 0, 4)
+        );
+        let code = ",[->+<<]>-<.";
+        assert_eq!(
+            "loop while nonzero, subtract 1, move right 1, add 1, move left 2, end loop",
+            Interpreter::explain_range(code, 1, 8)
+        );
+        assert_eq!(
+            "end loop, move right 1, subtract 1, move left 1, output the cell",
+            Interpreter::explain_range(code, 7, code.len())
+        );
+        assert_eq!("", Interpreter::explain_range("a<>b", 0, 4));
+        assert_eq!("add 2", Interpreter::explain_range("-++", 1, 10));
+        for (start, end) in [(5, 10), (2, 1)] {
+            assert_eq!("", Interpreter::explain_range("-++", start, end));
+        }
+    }
+
+    #[test]
+    fn test_output_is_constant() {
+        let code = "++++++++[>++++++++<-]>+.+.[-]++++++[-->+++<]>.[-][,.]<<";
+        for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let inter = Interpreter::build_opt(code, opt).unwrap();
+            assert_eq!(Some(vec![65, 66, 9]), inter.output_is_constant());
+        }
+        let inter = Interpreter::build(include_str!("../brainfuck/helloworld.bf")).unwrap();
+        assert_eq!(None, inter.output_is_constant());
+        for code in [",.", "+[-.]", "+++[-->+<]", "-[>+<-]<", "+[>]", ">.<<"] {
+            let inter = Interpreter::build(code).unwrap();
+            assert_eq!(None, inter.output_is_constant(), "{}", code);
+        }
+        let inter = Interpreter::from_ops(vec![
+            Move { d: 1 },
+            AddAt {
+                off: isize::MAX,
+                d: 1,
+            },
+        ]);
+        assert_eq!(None, inter.output_is_constant());
+    }
+
+    #[test]
+    fn test_basic_blocks() {
+        let inter = Interpreter::build("+[>.<-]").unwrap();
+        assert_eq!(vec![0..2, 2..7], inter.basic_blocks());
+        let inter = Interpreter::build("+[>.<-]+").unwrap();
+        assert_eq!(vec![0..2, 2..7, 7..8], inter.basic_blocks());
+        let inter = Interpreter::build_with(
+            ",@[.]",
+            &BuildOptions {
+                halt: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(vec![0..2, 2..3, 3..5], inter.basic_blocks());
+        assert!(Interpreter::build("").unwrap().basic_blocks().is_empty());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let inter = Interpreter::build("+[>.<-]").unwrap();
+        let dot = inter.to_dot();
+        assert!(dot.starts_with("digraph program {"));
+        let blocks = dot
+            .lines()
+            .filter(|line| line.contains("[label=") && !line.contains("->"))
+            .count();
+        assert_eq!(2, blocks);
+        assert_eq!(4, dot.matches(" -> ").count());
+        assert!(dot.contains("n2 -> n2 [label=\"nonzero\"];"));
+        assert!(dot.contains("n0 -> exit [label=\"zero\"];"));
+
+        let dot = Interpreter::build("").unwrap().to_dot();
+        assert_eq!(0, dot.matches(" -> ").count());
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut builder = IncrementalBuilder::new(BuildOptions::default());
+        let code = "++[>+++<-]\n>[>+<-]>.";
+        let inter = builder.rebuild(code, 0).unwrap();
+        assert_eq!(0, builder.reused());
+        assert_eq!(Interpreter::build(code).unwrap().ops, inter.ops);
+
+        let code = "++[>+++<-]\n>[>+<-]>+.";
+        let inter = builder.rebuild(code, 19).unwrap();
+        assert_eq!(Interpreter::build(code).unwrap().ops, inter.ops);
+        assert_eq!(14, builder.reused());
+
+        let code = "++[>+++<-]\n>[>+<-]>++.";
+        let inter = builder.rebuild(code, 20).unwrap();
+        assert_eq!(Interpreter::build(code).unwrap().ops, inter.ops);
+        assert_eq!(15, builder.reused());
+
+        let code = "++[>+++<-]\n>[>+<-";
+        let err = builder.rebuild(code, 17).unwrap_err();
+        assert_eq!("bracket not closed, line = 2, col = 2", err.to_string());
+        let code = "++[>+++<-]\n>[>+<-]]";
+        let err = builder.rebuild(code, 17).unwrap_err();
+        assert_eq!(Interpreter::build(code).unwrap_err(), err);
+    }
+
+    #[test]
+    fn test_bytecode() {
+        let code = include_str!("../brainfuck/mandelbrot.bf");
+        let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+        let bytes = inter.to_bytecode();
+        assert!(bytes.len() < inter.len() * 2);
+        let decoded = Interpreter::from_bytecode(&bytes).unwrap();
+        assert_eq!(inter.ops, decoded.ops);
+
+        assert!(Interpreter::from_bytecode(&[]).unwrap().is_empty());
+        let err = Interpreter::from_bytecode(&[4, 2, 5, 5]).unwrap_err();
+        assert_eq!("invalid bytecode at byte 3", err.to_string());
+        assert!(Interpreter::from_bytecode(&[4, 2]).is_err());
+        assert!(Interpreter::from_bytecode(&[1]).is_err());
+        assert!(Interpreter::from_bytecode(&[99]).is_err());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut inter = Interpreter::build("+++[->+<] add").unwrap();
+        let metadata = inter.metadata();
+        assert_eq!("7", metadata["ops"]);
+        assert_eq!("13", metadata["source_len"]);
+        assert_eq!("O1", metadata["opt"]);
+
+        inter.set_metadata("hash", "abc");
+        inter.optimize();
+        assert_eq!("abc", inter.metadata()["hash"]);
+        assert_eq!("2", inter.metadata()["ops"]);
+        assert_eq!("O2", inter.metadata()["opt"]);
+
+        let decoded = Interpreter::from_bytecode(&inter.to_bytecode()).unwrap();
+        assert_eq!(inter.metadata(), decoded.metadata());
+        let decoded = Interpreter::from_bytecode(&bytecode::encode(&inter.ops)).unwrap();
+        assert_eq!(1, decoded.metadata().len());
+    }
+
+    #[test]
+    fn test_to_json() {
+        let inter = Interpreter::build("[-]").unwrap();
+        assert_eq!(
+            r#"[{"op":"jz","addr":3},{"op":"add","d":-1},{"op":"jnz","addr":1}]"#,
+            inter.to_json()
+        );
+        let inter = Interpreter::build_opt("[-]>[-]+>,[->++<]", OptLevel::O2).unwrap();
+        assert_eq!(
+            r#"[{"op":"clear_range","start_off":0,"len":2},{"op":"add_at","off":1,"d":1},{"op":"move","d":2},{"op":"in"},{"op":"mul_add","off":1,"factor":2},{"op":"clear"}]"#,
+            inter.to_json()
+        );
+        assert_eq!("[]", Interpreter::build("").unwrap().to_json());
+    }
+
+    #[test]
+    fn test_optimize() {
+        let ops = [
+            Add { d: 1 },
+            Add { d: 2 },
+            Move { d: 1 },
+            Move { d: -1 },
+            JmpZ { addr: 0 },
+            Add { d: -1 },
+            Move { d: 2 },
+            Add { d: 1 },
+            Move { d: -2 },
+            JmpNz { addr: 0 },
+            Out,
+        ];
+        let mut inter = Interpreter::from_bytecode(&bytecode::encode(&ops)).unwrap();
+        inter.optimize();
+        assert_eq!(vec![Add { d: 3 }, MoveAdd { off: 2 }, Out], inter.ops);
+
+        let code = include_str!("../brainfuck/helloworld.bf");
+        let mut inter = Interpreter::build_opt(code, OptLevel::O0).unwrap();
+        inter.optimize();
+        assert_eq!(
+            Interpreter::build_opt(code, OptLevel::O2).unwrap().ops,
+            inter.ops
+        );
+    }
+
+    #[test]
+    fn test_diff_output() {
+        let a = Interpreter::build(",[.,]").unwrap();
+        let b = Interpreter::build(",[.+.-,]").unwrap();
+        let c = Interpreter::build(",.,.,.").unwrap();
+        assert_eq!(Some(2), diff_output(&a, &b, b"xy\0"));
+        assert_eq!(None, diff_output(&a, &c, b"xyz\0"));
+        assert_eq!(Some(2), diff_output(&a, &c, b"xy\0"));
+        assert_eq!(None, diff_output(&a, &a, b""));
+    }
+
+    #[test]
+    fn test_build_from_reader() {
+        let code = include_str!("../brainfuck/mandelbrot.bf").repeat(20);
+        let options = BuildOptions::default();
+        let inter = Interpreter::build_from_reader(std::io::Cursor::new(&code), &options).unwrap();
+        assert_eq!(Interpreter::build(&code).unwrap().ops, inter.ops);
+
+        let options = BuildOptions {
+            opt: OptLevel::O2,
+            halt: true,
+            tab_width: 4,
+            ..Default::default()
+        };
+        for code in ["+[-]@.", "+\n\t+ ]", "[\n[]x[", "a+-[", "[]]", "\t\t]"] {
+            let built = Interpreter::build_with(code, &options);
+            match Interpreter::build_from_reader(code.as_bytes(), &options) {
+                Ok(inter) => assert_eq!(built.unwrap().ops, inter.ops),
+                Err(err) => assert_eq!(built.unwrap_err(), err),
+            }
+        }
+
+        // Folds, brackets and positions carry across reads of one byte.
+        struct Bytes<'a>(&'a [u8]);
+
+        impl Read for Bytes<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(1);
+                self.0.read(&mut buf[..n])
+            }
+        }
+
+        for code in ["++--+>><", "+[->+<]\n\t[", "[[]\n]]"] {
+            let built = Interpreter::build_with(code, &options);
+            match Interpreter::build_from_reader(Bytes(code.as_bytes()), &options) {
+                Ok(inter) => assert_eq!(built.unwrap().ops, inter.ops),
+                Err(err) => assert_eq!(built.unwrap_err(), err),
+            }
+        }
+
+        /// Fails partway through the source, after its first chunk.
+        struct Broken(bool);
+
+        impl Read for Broken {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if std::mem::replace(&mut self.0, true) {
+                    return Err(ErrorKind::BrokenPipe.into());
+                }
+                buf[..3].copy_from_slice(b"+\n+");
+                Ok(3)
+            }
+        }
+
+        let err = Interpreter::build_from_reader(Broken(false), &options).unwrap_err();
+        assert!(matches!(err.kind, BuildErrorKind::IO { .. }));
+        assert_eq!((2, 2), (err.line(), err.col()));
+    }
+
+    #[test]
+    fn test_io_equivalent() {
+        let a = Interpreter::build(",[.,]").unwrap();
+        let b = Interpreter::build(">,[.>,]").unwrap();
+        let inputs = [b"abc\0".to_vec(), b"\0".to_vec(), vec![]];
+        assert!(io_equivalent(&a, &b, &inputs, 1000));
+
+        let c = Interpreter::build(",[.,.]").unwrap();
+        assert!(!io_equivalent(&a, &c, &inputs, 1000));
+        assert!(io_equivalent(&a, &c, &[b"\0".to_vec()], 1000));
+
+        let forever = Interpreter::build("+[]").unwrap();
+        assert!(!io_equivalent(&a, &forever, &[vec![0]], 1000));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let a = Interpreter::build("++").unwrap();
+        let b = Interpreter::build("+ plus +").unwrap();
+        let c = Interpreter::build("+++").unwrap();
+        let d = Interpreter::build("++>").unwrap();
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), c.fingerprint());
+        assert_ne!(a.fingerprint(), d.fingerprint());
+        assert_ne!(
+            Interpreter::build("").unwrap().fingerprint(),
+            Interpreter::build("[]").unwrap().fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_unreachable_ops() {
+        let inter = Interpreter::build("+[>.<-]>[.]").unwrap();
+        assert!(inter.unreachable_ops().is_empty());
+
+        let options = BuildOptions {
+            halt: true,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with("+.@+.", &options).unwrap();
+        assert_eq!(vec![3, 4], inter.unreachable_ops());
+        let inter = Interpreter::build_with("+[.@]+.", &options).unwrap();
+        assert_eq!(vec![4], inter.unreachable_ops());
+    }
+
+    #[test]
+    fn test_loop_count() {
+        let inter = Interpreter::build_opt("+[>+[>+[.-]<-]<-]>[.-]>[.-]", OptLevel::O2).unwrap();
+        assert_eq!(5, inter.loop_count());
+        assert_eq!(3, inter.max_loop_depth());
+
+        let inter = Interpreter::build_opt("+[-]>[>]", OptLevel::O2).unwrap();
+        assert_eq!(0, inter.loop_count());
+        assert_eq!(0, inter.max_loop_depth());
+    }
+
+    #[test]
+    fn test_does_io() {
+        let inter = Interpreter::build("++[->+<]>[-]--").unwrap();
+        assert_eq!((false, false), inter.does_io());
+        let inter = Interpreter::build("+[,]").unwrap();
+        assert_eq!((true, false), inter.does_io());
+        let inter = Interpreter::build("+.").unwrap();
+        assert_eq!((false, true), inter.does_io());
+        let inter = Interpreter::build(",.").unwrap();
+        assert_eq!((true, true), inter.does_io());
+    }
+
+    #[test]
+    fn test_is_straight_line() {
+        assert!(Interpreter::build("+++>--").unwrap().is_straight_line());
+        assert!(Interpreter::build("").unwrap().is_straight_line());
+        assert!(!Interpreter::build("+[-]").unwrap().is_straight_line());
+        let inter = Interpreter::build_opt("+[-]", OptLevel::O2).unwrap();
+        assert!(inter.is_straight_line());
+    }
+
+    #[test]
+    fn test_net_effect() {
+        let inter = Interpreter::build(">+++<--").unwrap();
+        assert_eq!(Some(BTreeMap::from([(0, -2), (1, 3)])), inter.net_effect());
+        let inter = Interpreter::build_opt(">+++<--> >-<<+>+<", OptLevel::O2).unwrap();
+        assert_eq!(
+            Some(BTreeMap::from([(0, -1), (1, 4), (2, -1)])),
+            inter.net_effect()
+        );
+        assert_eq!(
+            Some(BTreeMap::new()),
+            Interpreter::build("").unwrap().net_effect()
+        );
+        for code in ["+[-]", "+.", ",", "[-]"] {
+            let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+            assert_eq!(None, inter.net_effect(), "{}", code);
+        }
+        let inter = Interpreter::from_ops(vec![Move { d: isize::MAX }, Move { d: 1 }]);
+        assert_eq!(None, inter.net_effect());
+        let inter = Interpreter::from_ops(vec![Add { d: isize::MAX }, Add { d: 1 }]);
+        assert_eq!(None, inter.net_effect());
+    }
+
     #[test]
     fn test_not_match() {
         let code = r#"[[
@@ -311,21 +4184,431 @@ mod tests {
             },
             err
         );
+        assert_eq!("bracket not match, line = 2, col = 3", err.to_string());
+    }
+
+    #[test]
+    fn test_not_closed() {
+        let code = r#"[[[
+]]++"#;
+        let err = Interpreter::build(code).unwrap_err();
+        assert_eq!(
+            BuildError {
+                line: 1,
+                col: 1,
+                kind: BuildErrorKind::BracketNotClosed,
+            },
+            err
+        );
+    }
+
+    #[test]
+    fn test_halt() {
+        let code = "+++@---.";
+        let inter = Interpreter::build(code).unwrap();
+        assert!(!inter.ops.contains(&Halt));
+
+        let options = BuildOptions {
+            halt: true,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with(code, &options).unwrap();
+        assert_eq!(Halt, inter.ops[1]);
+
+        let mut state = VmState::new();
+        let mut out = MockInOut::dummy();
+        inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut out,
+                &mut MockInOut::dummy(),
+            )
+            .unwrap();
+        assert_eq!(3, state.data[0]);
+        assert!(out.data.is_empty());
+    }
+
+    #[test]
+    fn test_debug() {
+        let code = "++>+++#.";
+        let mut out = MockInOut::dummy();
+        let mut debug = vec![];
+        let inter = Interpreter::build(code).unwrap();
+        inter
+            .execute_debug(&mut MockInOut::dummy(), &mut out, &mut debug)
+            .unwrap();
+        assert!(debug.is_empty());
+
+        let options = BuildOptions {
+            debug: true,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with(code, &options).unwrap();
+        inter
+            .execute_debug(&mut MockInOut::dummy(), &mut out, &mut debug)
+            .unwrap();
+        let dump = String::from_utf8(debug).unwrap();
+        assert!(dump.starts_with("ptr = 1, data[0..16] = [2, 3, 0,"));
+        assert_eq!(vec![3, 3], out.data.iter().copied().collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_print_ptr() {
+        let code = ">>!<!.";
+        let options = BuildOptions {
+            print_ptr: Some(b'!'),
+            opt: OptLevel::O2,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with(code, &options).unwrap();
+        let mut out = vec![];
+        let mut debug = vec![];
+        inter
+            .execute_debug(&mut MockInOut::dummy(), &mut out, &mut debug)
+            .unwrap();
+        assert_eq!("ptr = 2\nptr = 1\n", String::from_utf8(debug).unwrap());
+        assert_eq!(vec![0], out);
+        assert_eq!(
+            inter.ops,
+            Interpreter::from_bytecode(&inter.to_bytecode())
+                .unwrap()
+                .ops
+        );
+
+        let inter = Interpreter::build(code).unwrap();
+        assert!(!inter.ops.contains(&PrintPtr));
+    }
+
+    #[test]
+    fn test_swap_acc() {
+        let code = "+++^>++v.<.";
+        let inter = Interpreter::build(code).unwrap();
+        assert!(!inter.ops.contains(&SwapAcc));
+
+        let options = BuildOptions {
+            swap_acc: Some((b'^', b'v')),
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with(code, &options).unwrap();
+        let mut state = VmState::new();
+        let mut out = MockInOut::dummy();
+        inter
+            .run(&mut state, &mut MockInOut::dummy(), &mut out, &mut sink())
+            .unwrap();
+        assert_eq!(vec![3, 0], out.data.iter().copied().collect::<Vec<u8>>());
+        assert_eq!(2, state.acc());
+    }
+
+    #[test]
+    fn test_run_capture() {
+        let inter = Interpreter::build(",>++[<+>-]<.").unwrap();
+        let result = inter.run_capture(b"a").unwrap();
+        assert_eq!(b"c".to_vec(), result.output);
+        assert_eq!([b'c', 0], result.tape[..2]);
+        assert_eq!(TAPE_SIZE, result.tape.len());
+        assert_eq!(16, result.stats.steps);
+        assert!(inter.run_capture(b"").is_err());
+    }
+
+    #[test]
+    fn test_record_run() {
+        let echo = Interpreter::build(",[.,]").unwrap();
+        let recording = echo.record_run(b"hello\0").unwrap();
+        assert_eq!(b"hello".to_vec(), recording.output);
+        let bytes = recording.to_bytes();
+        assert_eq!(8 + 6 + 8 + 5, bytes.len());
+        let recording = Recording::from_bytes(&bytes).unwrap();
+        assert!(echo.replay_run(&recording).unwrap());
+
+        let shout = Interpreter::build(",[--------------------------------.,]").unwrap();
+        assert!(!shout.replay_run(&recording).unwrap());
+        assert!(echo.record_run(b"no end").is_err());
+
+        assert_eq!(None, Recording::from_bytes(&bytes[..bytes.len() - 1]));
+        assert_eq!(
+            None,
+            Recording::from_bytes(&[bytes.clone(), vec![0]].concat())
+        );
+        assert_eq!(None, Recording::from_bytes(&[]));
+    }
+
+    #[test]
+    fn test_output_len() {
+        let inter = Interpreter::build(include_str!("../brainfuck/helloworld.bf")).unwrap();
+        assert_eq!(13, inter.output_len(b"").unwrap());
+
+        let inter =
+            Interpreter::build("++++[>++++++++<-]>[>++++++++[>++++++++<-]>[.-]<<-]").unwrap();
+        assert_eq!(32 * 64, inter.output_len(b"").unwrap());
+        let inter = Interpreter::build(",[.,]").unwrap();
+        assert!(inter.output_len(b"abc").is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let inter = Interpreter::build(include_str!("../brainfuck/helloworld.bf")).unwrap();
+        assert_eq!(b"Hello World!\n".to_vec(), inter.eval().unwrap());
+
+        let inter = Interpreter::build("+.,.").unwrap();
+        let err = inter.eval().unwrap_err();
+        assert!(matches!(err.kind(), RuntimeErrorKind::IO { .. }));
+    }
+
+    #[test]
+    fn test_input_fn() {
+        let inter = Interpreter::build(",[.,]").unwrap();
+        let mut calls = 0;
+        let mut out = vec![];
+        let mut on_input = || {
+            calls += 1;
+            Some(b"abc\0"[calls - 1])
+        };
+        inter
+            .execute_with_input_fn(&mut on_input, &mut out)
+            .unwrap();
+        assert_eq!(b"abc".to_vec(), out);
+        assert_eq!(4, calls);
+
+        let inter = Interpreter::build(",.,.").unwrap();
+        let mut input = b"x".iter().copied();
+        let mut out = vec![];
+        let err = inter
+            .execute_with_input_fn(&mut || input.next(), &mut out)
+            .unwrap_err();
+        assert!(matches!(err.kind(), RuntimeErrorKind::IO { .. }));
+        assert_eq!(b"x".to_vec(), out);
+    }
+
+    #[test]
+    fn test_execute_timed() {
+        let code = include_str!("../brainfuck/helloworld.bf");
+        let inter = Interpreter::build(code).unwrap();
+        let mut out = MockInOut::dummy();
+        let (stats, elapsed) = inter
+            .execute_timed(&mut MockInOut::dummy(), &mut out)
+            .unwrap();
+        assert!(stats.steps > inter.ops.len() as u64);
+        assert!(elapsed > Duration::ZERO);
+        assert_eq!(13, out.data.len());
+
+        let inter = Interpreter::build("+++").unwrap();
+        let (stats, _) = inter
+            .execute_timed(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+            .unwrap();
+        assert_eq!(ExecStats { steps: 1 }, stats);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let inter = Interpreter::build("+[>+<]").unwrap();
+        let start = Instant::now();
+        let err = inter
+            .execute_timeout(
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                Duration::from_millis(10),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err.kind(), RuntimeErrorKind::Timeout { elapsed } if *elapsed > Duration::from_millis(10))
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(err.to_string().starts_with("timed out after "));
+
+        let inter = Interpreter::build("+++.").unwrap();
+        let stats = inter
+            .execute_timeout(
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                Duration::from_secs(5),
+            )
+            .unwrap();
+        assert_eq!(2, stats.steps);
+    }
+
+    #[test]
+    fn test_cancellable() {
+        let inter = Interpreter::build("+[>+<]").unwrap();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let start = Instant::now();
+        let err = inter
+            .execute_cancellable(
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                cancel,
+                100,
+            )
+            .unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!("cancelled", err.to_string());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let inter = Interpreter::build("+++.").unwrap();
+        let mut out = MockInOut::dummy();
+        inter
+            .execute_cancellable(
+                &mut MockInOut::dummy(),
+                &mut out,
+                Arc::new(AtomicBool::new(false)),
+                1,
+            )
+            .unwrap();
+        assert_eq!(1, out.data.len());
+    }
+
+    #[test]
+    fn test_limited() {
+        let inter = Interpreter::build("+[>+<]").unwrap();
+        let err = inter
+            .execute_limited(&mut MockInOut::dummy(), &mut MockInOut::dummy(), 100)
+            .unwrap_err();
+        assert_eq!("step limit exceeded, steps = 100", err.to_string());
+        assert!(!inter.will_halt_within(b"", 10000).unwrap());
+
+        let inter = Interpreter::build("++[>+<-],.").unwrap();
+        assert!(inter.will_halt_within(b"a", 100).unwrap());
+        assert!(!inter.will_halt_within(b"a", 3).unwrap());
+        let err = inter.will_halt_within(b"", 100).unwrap_err();
+        assert!(matches!(err.kind(), RuntimeErrorKind::IO { .. }));
+    }
+
+    #[test]
+    fn test_gas() {
+        let inter = Interpreter::build(",.,.,.+++>+").unwrap();
+        let schedule = GasSchedule::default();
+        let left = inter
+            .execute_gas(
+                &mut MockInOut::new(b"abc".to_vec()),
+                &mut sink(),
+                10,
+                &schedule,
+            )
+            .unwrap();
+        assert_eq!(1, left);
+
+        let schedule = GasSchedule {
+            input: 3,
+            output: 3,
+            ..Default::default()
+        };
+        let err = inter
+            .execute_gas(
+                &mut MockInOut::new(b"abc".to_vec()),
+                &mut sink(),
+                10,
+                &schedule,
+            )
+            .unwrap_err();
+        assert_eq!("out of gas", err.to_string());
+        let left = inter
+            .execute_gas(
+                &mut MockInOut::new(b"abc".to_vec()),
+                &mut sink(),
+                30,
+                &schedule,
+            )
+            .unwrap();
+        assert_eq!(9, left);
+    }
+
+    #[test]
+    fn test_traced() {
+        let inter = Interpreter::build_opt("++[>+<-]>.", OptLevel::O2).unwrap();
+        let mut trace = vec![];
+        inter
+            .execute_traced(
+                &mut VmState::new(),
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &Config::default(),
+                &mut trace,
+            )
+            .unwrap();
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            vec![
+                "ip = 0, op = Add { d: 2 }, ptr = 0, cell = 0",
+                "ip = 1, op = MoveAdd { off: 1 }, ptr = 0, cell = 2",
+                "ip = 2, op = Move { d: 1 }, ptr = 0, cell = 0",
+                "ip = 3, op = Out, ptr = 1, cell = 2",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn test_profiled() {
+        let inter = Interpreter::build_opt("+>++>++>+++>>[-]<+-", OptLevel::O2).unwrap();
+        let profile = inter
+            .execute_profiled(&mut MockInOut::dummy(), &mut MockInOut::dummy(), true)
+            .unwrap();
+        assert_eq!(5, profile.distinct_cells);
+        assert_eq!(
+            Some(BTreeMap::from([(1, 1), (2, 2), (3, 1)])),
+            profile.histogram
+        );
+        assert_eq!(inter.ops.len() as u64, profile.stats.steps);
+
+        let profile = inter
+            .execute_profiled(&mut MockInOut::dummy(), &mut MockInOut::dummy(), false)
+            .unwrap();
+        assert_eq!(None, profile.histogram);
+        assert_eq!(vec![0, 1, 2, 3, 5], profile.written_cells);
+
+        for opt in [OptLevel::O0, OptLevel::O2] {
+            let inter = Interpreter::build_opt(">.<+>>->>>+[>+<-]>.[-]<<<<<[>]", opt).unwrap();
+            let profile = inter
+                .execute_profiled(&mut MockInOut::dummy(), &mut MockInOut::dummy(), false)
+                .unwrap();
+            assert_eq!(vec![0, 2, 5, 6], profile.written_cells);
+            assert_eq!(5, profile.distinct_cells);
+        }
+
+        let inter = Interpreter::build("+++++[>++[>+<-]<-]+[-]").unwrap();
+        let profile = inter
+            .execute_profiled(&mut MockInOut::dummy(), &mut MockInOut::dummy(), false)
+            .unwrap();
+        assert_eq!(
+            BTreeMap::from([(1, 5), (4, 10), (14, 1)]),
+            profile.loop_iterations
+        );
     }
 
     #[test]
-    fn test_not_closed() {
-        let code = r#"[[[
-]]++"#;
-        let err = Interpreter::build(code).unwrap_err();
-        assert_eq!(
-            BuildError {
-                line: 1,
-                col: 1,
-                kind: BuildErrorKind::BracketNotClosed,
-            },
-            err
-        );
+    fn test_repeated() {
+        let inter = Interpreter::build(",[.,]").unwrap();
+        let results = inter.execute_repeated(&[b"ab\0".to_vec(), b"abcd\0".to_vec(), vec![]]);
+        assert_eq!(3, results.len());
+        let short = results[0].as_ref().unwrap();
+        let long = results[1].as_ref().unwrap();
+        assert!(short.steps < long.steps);
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_execute_with() {
+        let code = include_str!("../brainfuck/helloworld.bf");
+        let inter = Interpreter::build(code).unwrap();
+        let mut expected = MockInOut::dummy();
+        inter
+            .execute(&mut MockInOut::dummy(), &mut expected)
+            .unwrap();
+
+        let mut out: Vec<u8> = vec![];
+        inter.execute_with(&mut std::io::empty(), &mut out).unwrap();
+        assert_eq!(expected.data, out);
+
+        let inter = Interpreter::build(",>,<[->+<]>.").unwrap();
+        let mut out: Vec<u8> = vec![];
+        inter.execute_with(&mut &b"!!"[..], &mut out).unwrap();
+        assert_eq!(b"B".to_vec(), out);
     }
 
     #[test]
@@ -337,10 +4620,9 @@ mod tests {
         inter.execute(&mut input, &mut out).unwrap();
         assert_eq!(
             "ih".as_bytes(),
-            out.data.iter().map(|c| *c).collect::<Vec<u8>>()
+            out.data.iter().copied().collect::<Vec<u8>>()
         );
 
-
         let mut bad_input = MockInOut::bad();
         let mut bad_output = MockInOut::bad();
         let err = inter.execute(&mut bad_input, &mut bad_output).unwrap_err();
@@ -352,6 +4634,1047 @@ mod tests {
         assert_eq!("io err: write", err.to_string());
     }
 
+    #[test]
+    fn test_snapshot_on_error() {
+        let inter = Interpreter::build_opt("+>++>+++<<<<<", OptLevel::O2).unwrap();
+        let err = inter
+            .execute(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+            .unwrap_err();
+        assert_eq!(None, err.snapshot());
+
+        let config = Config {
+            snapshot_on_error: true,
+            ..Default::default()
+        };
+        let err = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut MockInOut::dummy(), &config)
+            .unwrap_err();
+        assert_eq!("data overflow, idx = -3", err.to_string());
+        let snapshot = err.snapshot().unwrap();
+        assert_eq!(0, snapshot.ptr);
+        assert_eq!(0, snapshot.start);
+        assert_eq!(16, snapshot.cells.len());
+        assert_eq!([1, 2, 3, 0], snapshot.cells[..4]);
+
+        let code = String::from_utf8(vec![b'+'; 9]).unwrap() + &">+".repeat(29999) + ">";
+        let inter = Interpreter::build_opt(&code, OptLevel::O2).unwrap();
+        let err = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut MockInOut::dummy(), &config)
+            .unwrap_err();
+        let snapshot = err.snapshot().unwrap();
+        assert_eq!(29991, snapshot.start);
+        assert_eq!(vec![1; 9], snapshot.cells);
+    }
+
+    #[test]
+    fn test_tape_size() {
+        let config = Config {
+            tape_size: 10,
+            ..Default::default()
+        };
+        let inter = Interpreter::build(">>>>>>>>>+.").unwrap();
+        let mut out = MockInOut::dummy();
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(1, out.data.len());
+
+        let inter = Interpreter::build(">>>>>>>>>>").unwrap();
+        let err = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut MockInOut::dummy(), &config)
+            .unwrap_err();
+        assert_eq!("data overflow, idx = 10", err.to_string());
+
+        let inter = Interpreter::build("+[>+]").unwrap();
+        let err = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut MockInOut::dummy(), &config)
+            .unwrap_err();
+        assert_eq!("data overflow, idx = 10", err.to_string());
+
+        let err = inter
+            .execute_with_config(
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &Config::default(),
+            )
+            .unwrap_err();
+        assert_eq!("data overflow, idx = 30000", err.to_string());
+    }
+
+    #[test]
+    fn test_classic() {
+        let config = Config::classic();
+        let inter = Interpreter::build("<+++[-<+>]>[-]<<.").unwrap();
+        let mut state = VmState::with_config(&config);
+        inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut sink(),
+            )
+            .unwrap();
+        assert_eq!(29998, state.ptr);
+        assert_eq!(3, state.data[29998]);
+        assert_eq!(0, state.data[29999]);
+
+        let code = ">".repeat(30000) + "+<[-]>>[>]<.";
+        let inter = Interpreter::build(&code).unwrap();
+        let mut state = VmState::with_config(&config);
+        inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut sink(),
+            )
+            .unwrap();
+        assert_eq!(1, state.data[0]);
+        assert_eq!(0, state.ptr);
+
+        let inter = Interpreter::build("+++,.").unwrap();
+        let mut out = MockInOut::dummy();
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![3], out.data.iter().copied().collect::<Vec<u8>>());
+
+        let config = Config {
+            eof: EofMode::Zero,
+            ..Default::default()
+        };
+        let mut out = MockInOut::dummy();
+        inter
+            .execute_with_config(&mut MockInOut::new(vec![]), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![0], out.data.iter().copied().collect::<Vec<u8>>());
+        assert!(inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &Config::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let config = Config {
+            tape_size: 10,
+            pointer_mode: PointerMode::Clamp,
+            ..Default::default()
+        };
+        let inter = Interpreter::build("+<<<<<<<<<<<<+.>+.").unwrap();
+        let mut out = MockInOut::dummy();
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![2, 1], out.data.iter().copied().collect::<Vec<u8>>());
+
+        let inter = Interpreter::build(">>>>>>>>>>>>>>>+.").unwrap();
+        let mut state = VmState::with_config(&config);
+        inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut sink(),
+            )
+            .unwrap();
+        assert_eq!(9, state.ptr);
+        assert_eq!(1, state.data[9]);
+
+        let run = |opt, pointer_mode| {
+            let options = BuildOptions {
+                opt,
+                pointer_mode,
+                ..Default::default()
+            };
+            let inter = Interpreter::build_with("<+>+.<.>>[-]>[-]<<<[->+<]>.", &options).unwrap();
+            let mut out = vec![];
+            let config = Config::new().tape_size(3).pointer_mode(PointerMode::Clamp);
+            inter
+                .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+                .map(|_| out)
+        };
+        for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            let out = run(opt, PointerMode::Clamp).unwrap();
+            assert_eq!(vec![1, 1, 2], out, "{:?}", opt);
+        }
+        // Built for another mode, O2 adds at an offset instead of moving there.
+        assert_eq!(
+            &RuntimeErrorKind::DataOverflow { idx: -1 },
+            run(OptLevel::O2, PointerMode::Error).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_grow() {
+        let config = Config::new()
+            .tape_size(4)
+            .pointer_mode(PointerMode::Grow)
+            .max_tape(20);
+        let run = |code: &str| {
+            let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+            let mut state = VmState::with_config(&config);
+            let result = inter.run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut sink(),
+            );
+            (result, state)
+        };
+
+        let (result, state) = run("+>>>>>+>>>+[-<+>]<[>+<-]<.");
+        result.unwrap();
+        assert_eq!(16, state.data.len());
+        assert_eq!([1, 0, 0, 0, 0, 1, 0, 0, 1], state.data[..9]);
+
+        let (result, state) = run("+[>+]");
+        assert_eq!(
+            &RuntimeErrorKind::DataOverflow { idx: 20 },
+            result.unwrap_err().kind()
+        );
+        assert_eq!(20, state.data.len());
+        assert_eq!(19, state.ptr);
+
+        let (result, state) = run(">>>>>>>>>>>>>>>>>>>[-]>[-]");
+        assert!(result.is_err());
+        assert_eq!(20, state.data.len());
+        assert!(run("<").0.is_err());
+    }
+
+    #[test]
+    fn test_execute_growing() {
+        let config = Config::new().tape_size(4).max_tape(20);
+        let grow = |code: &str| {
+            let inter = Interpreter::build(code).unwrap();
+            let mut sizes = vec![];
+            let result = inter.execute_growing(
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &config,
+                &mut |len| sizes.push(len),
+            );
+            (result, sizes)
+        };
+        let (result, sizes) = grow("+>>>>>+>>>>>>>>>>+>>>");
+        result.unwrap();
+        assert_eq!(vec![8, 16, 20], sizes);
+
+        let (result, sizes) = grow(">>>>>>>>>>>>>>>>>>>+>");
+        assert!(result.is_err());
+        assert_eq!(vec![20], sizes);
+        assert_eq!(Vec::<usize>::new(), grow(">>>").1);
+    }
+
+    #[test]
+    fn test_run_until_output() {
+        let prompt = "++++++++[>++++++++<-]>--.<+++++[>------<-]>.[-]";
+        let code = prompt.to_string() + ",[.,]" + prompt;
+        let inter = Interpreter::build_opt(&code, OptLevel::O2).unwrap();
+        let mut state = VmState::new();
+        let mut input = &b"ab\0"[..];
+        let mut out = vec![];
+
+        assert!(inter
+            .run_until_output(&mut state, &mut input, &mut out, b"> ")
+            .unwrap());
+        assert_eq!(b"> ".to_vec(), out);
+        assert_eq!(3, input.len());
+        assert!(inter
+            .run_until_output(&mut state, &mut input, &mut out, b"> ")
+            .unwrap());
+        assert_eq!(b"> ab> ".to_vec(), out);
+        assert!(!inter
+            .run_until_output(&mut state, &mut input, &mut out, b"> ")
+            .unwrap());
+        assert_eq!(inter.len(), state.ip);
+
+        // An `OutRange` stops at the byte completing the needle.
+        for opt in [OptLevel::O1, OptLevel::O2] {
+            let inter = Interpreter::build_opt("+++++[>.+<-]", opt).unwrap();
+            let mut state = VmState::new();
+            let mut out = vec![];
+            assert!(inter
+                .run_until_output(&mut state, &mut std::io::empty(), &mut out, &[1])
+                .unwrap());
+            assert_eq!(vec![0, 1], out, "{:?}", opt);
+            assert!(!inter
+                .run_until_output(&mut state, &mut std::io::empty(), &mut out, &[9])
+                .unwrap());
+            assert_eq!(vec![0, 1, 2, 3, 4], out, "{:?}", opt);
+        }
+    }
+
+    #[test]
+    fn test_bit_cells() {
+        let config = Config::new().cell_mode(CellMode::Bit);
+        let inter = Interpreter::build("+.+.+++.>.-.<.--.>[->+<]>.").unwrap();
+        let mut out = vec![];
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![1, 1, 1, 0, 0, 1, 0, 0], out);
+
+        let inter = Interpreter::build_opt("+>+++++[<+>-]<[>++<-]>.", OptLevel::O2).unwrap();
+        let mut out = vec![];
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![1], out);
+
+        // Bit cells count `+++` as 1, and `[+]` never leaves a set cell.
+        let config = config.max_steps(1000);
+        let run = |code: &str, opt| {
+            let options = BuildOptions {
+                opt,
+                checked_cells: true,
+                ..Default::default()
+            };
+            let inter = Interpreter::build_with(code, &options).unwrap();
+            let mut out = vec![];
+            inter
+                .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+                .map(|_| out)
+                .map_err(|err| err.to_string())
+        };
+        for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+            assert_eq!(Ok(vec![0]), run("+++>[-]<-[->+<]>.", opt), "{:?}", opt);
+            assert_eq!(
+                Err("step limit exceeded, steps = 1000".to_string()),
+                run("+[+].", opt),
+                "{:?}",
+                opt
+            );
+        }
+    }
+
+    #[test]
+    fn test_output_preview() {
+        let inter = Interpreter::build("+[.+]").unwrap();
+        let mut out = vec![];
+        let config = Config::new().output_preview(100);
+        let stats = inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!((1..=100).collect::<Vec<u8>>(), out);
+        assert_eq!(300, stats.steps);
+
+        let inter = Interpreter::build("+[.]").unwrap();
+        let mut out = vec![];
+        let config = Config::new()
+            .output_preview(5)
+            .max_steps(100)
+            .output_chunk(4);
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![1; 5], out);
+
+        let inter = Interpreter::build(".+.").unwrap();
+        let mut out = vec![];
+        let config = Config::new().output_preview(5);
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![0, 1], out);
+
+        for opt in [OptLevel::O1, OptLevel::O2] {
+            let inter = Interpreter::build_opt("+++++[>.+<-]", opt).unwrap();
+            let mut out = vec![];
+            let config = Config::new().output_preview(2);
+            inter
+                .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+                .unwrap();
+            assert_eq!(vec![0, 1], out, "{:?}", opt);
+        }
+
+        // A number in decimal counts once, however many bytes it takes.
+        let inter = Interpreter::build("++++++++++[.+]").unwrap();
+        let mut out = vec![];
+        let config = Config::new()
+            .output_preview(2)
+            .output_format(OutputFormat::Decimal);
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!(b"10 11 ".to_vec(), out);
+    }
+
+    #[test]
+    fn test_pointer_overflow() {
+        assert_eq!(Some(usize::MAX - 1), on_tape(usize::MAX - 2, 1, usize::MAX));
+        assert_eq!(None, on_tape(usize::MAX - 1, 1, usize::MAX));
+        assert_eq!(None, on_tape(usize::MAX, 1, usize::MAX));
+        assert_eq!(None, on_tape(0, -1, 10));
+        assert_eq!(None, on_tape(0, isize::MIN, 10));
+        assert_eq!(Some(0), on_tape(isize::MAX as usize, isize::MIN + 1, 10));
+        assert_eq!(
+            Some(isize::MAX as usize + 1),
+            on_tape(1, isize::MAX, usize::MAX)
+        );
+
+        for d in [isize::MAX, isize::MIN] {
+            let ops = [Move { d: 1 }, Move { d }, AddAt { off: d, d: 1 }];
+            let inter = Interpreter::from_bytecode(&bytecode::encode(&ops)).unwrap();
+            let err = inter
+                .execute(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+                .unwrap_err();
+            let idx = if d > 0 { isize::MAX } else { isize::MIN + 1 };
+            assert_eq!(&RuntimeErrorKind::DataOverflow { idx }, err.kind());
+        }
+        let config = Config::new().tape_size(10).pointer_mode(PointerMode::Clamp);
+        let ops = [Move { d: 5 }, Move { d: isize::MAX }, Add { d: 1 }];
+        let inter = Interpreter::from_bytecode(&bytecode::encode(&ops)).unwrap();
+        let mut state = VmState::with_config(&config);
+        inter
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut sink(),
+            )
+            .unwrap();
+        assert_eq!(1, state.data[9]);
+    }
+
+    #[test]
+    fn test_decimal_output() {
+        let inter = Interpreter::build("++++++++++[>++++++++++++++++++++<-]>.<.+.").unwrap();
+        let mut out = vec![];
+        let config = Config::new().output_format(OutputFormat::Decimal);
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &config)
+            .unwrap();
+        assert_eq!("200 0 1 ", String::from_utf8(out).unwrap());
+
+        let mut out = vec![];
+        inter
+            .execute_with_config(&mut MockInOut::dummy(), &mut out, &Config::new())
+            .unwrap();
+        assert_eq!(vec![200, 0, 1], out);
+    }
+
+    #[test]
+    fn test_non_blocking_input() {
+        struct Pending;
+
+        impl Read for Pending {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let inter = Interpreter::build("+++,.").unwrap();
+        for (eof, expected) in [(EofMode::Unchanged, 3), (EofMode::Zero, 0)] {
+            let config = Config {
+                eof,
+                ..Default::default()
+            };
+            let mut out = vec![];
+            inter
+                .execute_with_config(&mut Pending, &mut out, &config)
+                .unwrap();
+            assert_eq!(vec![expected], out);
+            let mut out = vec![];
+            inter
+                .execute_with_config(&mut std::io::empty(), &mut out, &config)
+                .unwrap();
+            assert_eq!(vec![expected], out);
+        }
+        let err = inter
+            .execute_with_config(&mut Pending, &mut sink(), &Config::default())
+            .unwrap_err();
+        assert!(matches!(err.kind(), RuntimeErrorKind::IO { .. }));
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = Config::new()
+            .tape_size(4)
+            .pointer_mode(PointerMode::Wrap)
+            .eof(EofMode::Zero)
+            .initial_cells(vec![5, 6])
+            .snapshot_on_error(true);
+        let inter = Interpreter::build(".>.>>>.,.").unwrap();
+        let mut out = vec![];
+        let stats = inter
+            .execute_with_config(&mut std::io::empty(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![5, 6, 5, 0], out);
+        assert_eq!(7, stats.steps);
+
+        let config = config.max_steps(5);
+        let err = inter
+            .execute_with_config(&mut std::io::empty(), &mut sink(), &config)
+            .unwrap_err();
+        assert_eq!("step limit exceeded, steps = 5", err.to_string());
+        assert_eq!(Some(0), err.snapshot().map(|snapshot| snapshot.ptr));
+    }
+
+    #[test]
+    fn test_input_chunk() {
+        /// Records the size of every read.
+        struct Reads<'a>(&'a [u8], Vec<usize>);
+
+        impl Read for Reads<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.read(buf)?;
+                self.1.push(n);
+                Ok(n)
+            }
+        }
+
+        let code = ">,[>,]<[.<]";
+        let inter = Interpreter::build(code).unwrap();
+        let input = b"buffered input";
+        let config = Config::new().eof(EofMode::Zero);
+        let mut expected = vec![];
+        let mut read = Reads(input, vec![]);
+        inter
+            .execute_with_config(&mut read, &mut expected, &config)
+            .unwrap();
+        assert_eq!(input.len() + 1, read.1.len());
+
+        for chunk in [2, 5, 1000] {
+            let mut out = vec![];
+            let mut read = Reads(input, vec![]);
+            let config = config.clone().input_chunk(chunk);
+            inter
+                .execute_with_config(&mut read, &mut out, &config)
+                .unwrap();
+            assert_eq!(expected, out);
+            assert!(read.1.len() <= input.len().div_ceil(chunk) + 1);
+        }
+
+        // The output so far is written before every refill, here of one
+        // byte each.
+        struct Shared<'a>(&'a RefCell<Vec<u8>>);
+
+        impl Write for Shared<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = RefCell::new(vec![]);
+        let mut written = vec![];
+        let mut input = &b"ab"[..];
+        let mut next = || {
+            written.push(out.borrow().len());
+            input.split_first().map(|(&byte, rest)| {
+                input = rest;
+                byte
+            })
+        };
+        let inter = Interpreter::build(">++++++[<++++++++++>-]<.,.,.").unwrap();
+        let config = Config::new().input_chunk(16).output_chunk(16);
+        inter
+            .execute_with_config(&mut FnInput(&mut next), &mut Shared(&out), &config)
+            .unwrap();
+        assert_eq!(b"<ab".to_vec(), *out.borrow());
+        assert_eq!(vec![1, 2], written);
+    }
+
+    #[test]
+    fn test_crlf_input() {
+        let inter = Interpreter::build(",.,.,.,.").unwrap();
+        let config = Config::new().eof(EofMode::Zero);
+        let mut out = vec![];
+        inter
+            .execute_with_config(&mut &b"a\r\nb"[..], &mut out, &config)
+            .unwrap();
+        assert_eq!(b"a\r\nb".to_vec(), out);
+
+        let mut out = vec![];
+        inter
+            .execute_with_config(&mut &b"a\r\nb"[..], &mut out, &config.crlf_input())
+            .unwrap();
+        assert_eq!(b"a\nb\0".to_vec(), out);
+    }
+
+    #[test]
+    fn test_short_write() {
+        /// Accepts nothing on the first write.
+        struct Reluctant {
+            refused: bool,
+            out: Vec<u8>,
+        }
+
+        impl Write for Reluctant {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if !self.refused {
+                    self.refused = true;
+                    return Ok(0);
+                }
+                self.out.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let inter = Interpreter::build("+++.+.").unwrap();
+        let mut out = Reluctant {
+            refused: false,
+            out: vec![],
+        };
+        let err = inter
+            .execute_with_config(&mut std::io::empty(), &mut out, &Config::new())
+            .unwrap_err();
+        assert!(matches!(err.kind(), RuntimeErrorKind::IO { .. }));
+        assert!(out.out.is_empty());
+
+        let mut out = Reluctant {
+            refused: false,
+            out: vec![],
+        };
+        inter
+            .execute_with_config(
+                &mut std::io::empty(),
+                &mut out,
+                &Config::new().write_retries(1),
+            )
+            .unwrap();
+        assert_eq!(vec![3, 4], out.out);
+
+        // Chunks written at once and decimal output retry the same way.
+        let chunked = Config::new().output_chunk(4);
+        let decimal = Config::new().output_format(OutputFormat::Decimal);
+        for (config, expected) in [(chunked, vec![3, 4]), (decimal, b"3 4 ".to_vec())] {
+            let mut out = Reluctant {
+                refused: false,
+                out: vec![],
+            };
+            let err = inter
+                .execute_with_config(&mut std::io::empty(), &mut out, &config)
+                .unwrap_err();
+            assert!(matches!(err.kind(), RuntimeErrorKind::IO { .. }));
+
+            let mut out = Reluctant {
+                refused: false,
+                out: vec![],
+            };
+            inter
+                .execute_with_config(&mut std::io::empty(), &mut out, &config.write_retries(1))
+                .unwrap();
+            assert_eq!(expected, out.out);
+        }
+    }
+
+    #[test]
+    fn test_output_chunk() {
+        /// Records the size of every write.
+        struct Writes(Vec<Vec<u8>>);
+
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let code = include_str!("../brainfuck/helloworld.bf").to_string() + ",.>+++[->++++<]>.";
+        let inter = Interpreter::build(&code).unwrap();
+        let mut expected = Writes(vec![]);
+        inter
+            .execute_with_config(&mut &b"!"[..], &mut expected, &Config::new())
+            .unwrap();
+        assert!(expected.0.iter().all(|write| write.len() == 1));
+        let expected = expected.0.concat();
+
+        for chunk in [1, 2, 5, 1000] {
+            let mut out = Writes(vec![]);
+            inter
+                .execute_with_config(&mut &b"!"[..], &mut out, &Config::new().output_chunk(chunk))
+                .unwrap();
+            assert!(out.0.iter().all(|write| write.len() <= chunk));
+            assert_eq!(expected, out.0.concat());
+        }
+
+        let mut out = Writes(vec![]);
+        inter
+            .execute_with_config(&mut &b"!"[..], &mut out, &Config::new().output_chunk(1000))
+            .unwrap();
+        assert_eq!(2, out.0.len());
+        assert_eq!(b"!\x0c"[..], out.0[1][..]);
+    }
+
+    #[test]
+    fn test_cell_overflow() {
+        let config = Config::new().cell_overflow(CellOverflow::Error);
+        let inter = Interpreter::build(&"+".repeat(255)).unwrap();
+        inter
+            .execute_with_config(&mut std::io::empty(), &mut sink(), &config)
+            .unwrap();
+
+        let inter = Interpreter::build(&("+".repeat(255) + ">+<+")).unwrap();
+        let err = inter
+            .execute_with_config(&mut std::io::empty(), &mut sink(), &config)
+            .unwrap_err();
+        assert_eq!("cell overflow, idx = 0, value = 256", err.to_string());
+        inter
+            .execute_with_config(&mut std::io::empty(), &mut sink(), &Config::new())
+            .unwrap();
+
+        let mul = format!("+++[->{}<]", "+".repeat(100));
+        for code in ["-", ">-", "+[->-<]", &mul] {
+            let inter = Interpreter::build_opt(code, OptLevel::O2).unwrap();
+            let err = inter
+                .execute_with_config(&mut std::io::empty(), &mut sink(), &config)
+                .unwrap_err();
+            assert!(matches!(err.kind(), RuntimeErrorKind::CellOverflow { .. }));
+        }
+
+        // Checked cells build O2 like O1, so every level stops at the same step.
+        let run = |code: &str, opt| {
+            let options = BuildOptions {
+                opt,
+                checked_cells: true,
+                ..Default::default()
+            };
+            let inter = Interpreter::build_with(code, &options).unwrap();
+            let mut out = vec![];
+            inter
+                .execute_with_config(&mut std::io::empty(), &mut out, &config)
+                .map(|_| out)
+                .map_err(|err| err.to_string())
+        };
+        let full = format!(">{}<++[->+<]", "+".repeat(255));
+        let cases = [
+            (
+                "+[+].",
+                Err("cell overflow, idx = 0, value = 256".to_string()),
+            ),
+            (
+                &full,
+                Err("cell overflow, idx = 1, value = 256".to_string()),
+            ),
+            ("++[->+++<]>.", Ok(vec![6])),
+        ];
+        for (code, expected) in cases {
+            for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                assert_eq!(expected, run(code, opt), "{} {:?}", code, opt);
+            }
+        }
+        let inter = Interpreter::build_opt("+[+].", OptLevel::O2).unwrap();
+        let mut out = vec![];
+        inter
+            .execute_with_config(&mut std::io::empty(), &mut out, &config)
+            .unwrap();
+        assert_eq!(vec![0], out);
+    }
+
+    #[test]
+    fn test_large_add() {
+        let inter = Interpreter::build(&("+".repeat(1000) + ".")).unwrap();
+        let mut out = vec![];
+        inter.execute(&mut std::io::empty(), &mut out).unwrap();
+        assert_eq!(vec![232], out);
+
+        // 200 + isize::MAX is 199 modulo 256.
+        let asm = format!(
+            "add {}\nout\nadd_at 1, {}\nmul_add 1, {}\nmove 1\nout",
+            isize::MAX,
+            isize::MIN,
+            isize::MAX
+        );
+        let inter = Interpreter::from_asm(&asm).unwrap();
+        let mut out = vec![];
+        inter
+            .execute_with_config(
+                &mut std::io::empty(),
+                &mut out,
+                &Config::new().initial_cells(vec![200]),
+            )
+            .unwrap();
+        assert_eq!(vec![199, 57], out);
+    }
+
+    #[test]
+    fn test_execute_from() {
+        let inter = Interpreter::build("++++++++[>++++++++<-]>+.").unwrap();
+        assert!(inter.disassemble().contains("    2  move 1\n"));
+        let mut out = vec![];
+        inter
+            .execute_from(vec![3, 41], 2, &mut MockInOut::dummy(), &mut out)
+            .unwrap();
+        assert_eq!(b"B".to_vec(), out);
+
+        let mut out = vec![];
+        inter
+            .execute_from(vec![], inter.len(), &mut MockInOut::dummy(), &mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_execute_from_bad_ip() {
+        let inter = Interpreter::build("+.").unwrap();
+        let mut out = vec![];
+        let err = inter
+            .execute_from(vec![0], 3, &mut MockInOut::dummy(), &mut out)
+            .unwrap_err();
+        assert_eq!(&RuntimeErrorKind::BadIp { ip: 3 }, err.kind());
+        assert_eq!("ip past the end of the program, ip = 3", err.to_string());
+        assert!(out.is_empty());
+        let err = inter
+            .execute_from(vec![0], usize::MAX, &mut MockInOut::dummy(), &mut out)
+            .unwrap_err();
+        assert_eq!(&RuntimeErrorKind::BadIp { ip: usize::MAX }, err.kind());
+    }
+
+    #[test]
+    fn test_execute_clamped() {
+        let inter = Interpreter::build("<+>>>>.<<<<<-").unwrap();
+        let config = Config::new().tape_size(3);
+        let mut out = vec![];
+        let (result, events) = inter.execute_clamped(&mut MockInOut::dummy(), &mut out, &config);
+        assert_eq!(6, result.unwrap().steps);
+        assert_eq!(vec![0], out);
+        assert_eq!(
+            vec![
+                OverflowEvent { ip: 0, idx: -1 },
+                OverflowEvent { ip: 2, idx: 4 },
+                OverflowEvent { ip: 4, idx: -3 },
+            ],
+            events
+        );
+
+        let inter = Interpreter::build_opt("+++[->>>+<<<]>>.", OptLevel::O2).unwrap();
+        let mut out = vec![];
+        let (result, events) = inter.execute_clamped(&mut MockInOut::dummy(), &mut out, &config);
+        assert_eq!(
+            &RuntimeErrorKind::DataOverflow { idx: 3 },
+            result.unwrap_err().kind()
+        );
+        assert!(out.is_empty());
+        assert_eq!(vec![OverflowEvent { ip: 1, idx: 3 }], events);
+        let (result, events) =
+            inter.execute_clamped(&mut MockInOut::dummy(), &mut sink(), &Config::new());
+        assert!(result.is_ok());
+        assert!(events.is_empty());
+
+        // An offset past the end of `isize`.
+        let inter = Interpreter::from_ops(vec![
+            Move { d: 1 },
+            AddAt {
+                off: isize::MAX,
+                d: 1,
+            },
+        ]);
+        let (result, events) =
+            inter.execute_clamped(&mut MockInOut::dummy(), &mut sink(), &Config::new());
+        assert!(result.is_err());
+        assert_eq!(
+            vec![OverflowEvent {
+                ip: 1,
+                idx: isize::MAX
+            }],
+            events
+        );
+    }
+
+    #[test]
+    fn test_execute_range() {
+        let inter = Interpreter::build("++>[-]<.").unwrap();
+        assert_eq!(JmpZ { addr: 5 }, inter.ops[2]);
+        let mut state = VmState::with_config(&Config::new().initial_cells(vec![7]));
+        let mut out = vec![];
+        let stats = inter
+            .execute_range(2..5, &mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap();
+        assert_eq!(&[0, 0], &state.data()[..2]);
+        assert_eq!(5, state.ip());
+        assert_eq!(15, stats.steps);
+        assert!(out.is_empty());
+
+        // The jump back to the loop's start leaves the range.
+        let mut state = VmState::with_config(&Config::new().initial_cells(vec![7]));
+        let stats = inter
+            .execute_range(4..7, &mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap();
+        assert_eq!(7, state.data()[0]);
+        assert_eq!(3, state.ip());
+        assert_eq!(1, stats.steps);
+
+        for (start, end) in [(5, 9), (9, 9), (5, 2)] {
+            let mut state = VmState::new();
+            let err = inter
+                .execute_range(start..end, &mut state, &mut MockInOut::dummy(), &mut out)
+                .unwrap_err();
+            assert_eq!(&RuntimeErrorKind::BadRange { start, end }, err.kind());
+            assert_eq!(0, state.ip());
+            if start > end {
+                assert_eq!("bad op range, range = 5..2", err.to_string());
+            }
+        }
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_step() {
+        let inter = Interpreter::build("++[>+<-]>.").unwrap();
+        let mut state = VmState::new();
+        let mut out = MockInOut::dummy();
+        let mut steps = 0;
+        while inter
+            .step(&mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap()
+        {
+            steps += 1;
+            assert!(state.ip() <= inter.len());
+        }
+        assert_eq!(inter.len(), state.ip());
+        assert_eq!(1, state.ptr());
+        assert_eq!([0, 2], state.data()[..2]);
+        assert_eq!(2, out.data[0]);
+
+        let (stats, _) = inter
+            .execute_timed(&mut MockInOut::dummy(), &mut MockInOut::dummy())
+            .unwrap();
+        assert_eq!(stats.steps, steps);
+        assert!(!inter
+            .step(&mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap());
+
+        let options = BuildOptions {
+            halt: true,
+            ..Default::default()
+        };
+        let inter = Interpreter::build_with("+@+", &options).unwrap();
+        let mut state = VmState::new();
+        let mut out = MockInOut::dummy();
+        assert!(inter
+            .step(&mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap());
+        assert!(inter
+            .step(&mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap());
+        assert!(!inter
+            .step(&mut state, &mut MockInOut::dummy(), &mut out)
+            .unwrap());
+        assert_eq!(1, state.data()[0]);
+
+        let inter = Interpreter::build("<").unwrap();
+        assert!(inter
+            .step(&mut VmState::new(), &mut MockInOut::dummy(), &mut out)
+            .is_err());
+    }
+
+    #[test]
+    fn test_run_until_cell_change() {
+        let inter = Interpreter::build_opt("+>.>++<<[->>+<<]>>.", OptLevel::O2).unwrap();
+        let mut state = VmState::new();
+        let mut out = MockInOut::dummy();
+        assert!(inter
+            .run_until_cell_change(&mut state, &mut MockInOut::dummy(), &mut out, 2)
+            .unwrap());
+        assert_eq!(2, state.data[2]);
+        assert_eq!(4, state.ip);
+        assert_eq!(1, out.data.len());
+
+        assert!(inter
+            .run_until_cell_change(&mut state, &mut MockInOut::dummy(), &mut out, 2)
+            .unwrap());
+        assert_eq!(3, state.data[2]);
+        assert_eq!(0, state.data[0]);
+
+        assert!(!inter
+            .run_until_cell_change(&mut state, &mut MockInOut::dummy(), &mut out, 1)
+            .unwrap());
+        assert_eq!(inter.len(), state.ip);
+        assert_eq!(2, out.data.len());
+    }
+
+    #[test]
+    fn test_signed_data() {
+        let inter = Interpreter::build("->-[>+<-]>.").unwrap();
+        let mut state = VmState::new();
+        let mut out = vec![];
+        inter
+            .run(&mut state, &mut MockInOut::dummy(), &mut out, &mut sink())
+            .unwrap();
+        assert_eq!(vec![255], out);
+        assert_eq!([255, 0, 255], state.data[..3]);
+        assert_eq!([-1, 0, -1], state.signed_data()[..3]);
+
+        let mut state = VmState::new();
+        Interpreter::build("++++++++[>++++++++++++++++<-]>")
+            .unwrap()
+            .run(
+                &mut state,
+                &mut MockInOut::dummy(),
+                &mut sink(),
+                &mut sink(),
+            )
+            .unwrap();
+        assert_eq!(-128, state.signed_data()[1]);
+    }
+
+    #[test]
+    fn test_signed_cells() {
+        let config = Config::new().cell_mode(CellMode::Signed);
+        let inter = Interpreter::build("-.>+[-].").unwrap();
+        let mut state = VmState::with_config(&config);
+        let mut out = vec![];
+        inter
+            .run(&mut state, &mut MockInOut::dummy(), &mut out, &mut sink())
+            .unwrap();
+        assert_eq!(vec![255, 0], out);
+        assert_eq!([-1, 0], state.signed_data()[..2]);
+
+        // Overflow is outside -128..=127 rather than 0..=255.
+        let config = config.cell_overflow(CellOverflow::Error);
+        let run = |code: &str| {
+            Interpreter::build(code)
+                .unwrap()
+                .execute_with_config(&mut std::io::empty(), &mut sink(), &config)
+                .map_err(|err| err.to_string())
+        };
+        assert!(run(&"-".repeat(128)).is_ok());
+        assert!(run(&"+".repeat(127)).is_ok());
+        assert_eq!(
+            "cell overflow, idx = 0, value = -129",
+            run(&"-".repeat(129)).unwrap_err()
+        );
+        assert_eq!(
+            "cell overflow, idx = 0, value = 128",
+            run(&("+".repeat(127) + ">+<+")).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let inter = Interpreter::build("+>++").unwrap();
+        let before = VmState::new();
+        let mut after = before.clone();
+        inter
+            .run(
+                &mut after,
+                &mut MockInOut::dummy(),
+                &mut MockInOut::dummy(),
+                &mut sink(),
+            )
+            .unwrap();
+        let diff = before.diff(&after);
+        assert_eq!(vec![(0, 0, 1), (1, 0, 2)], diff.cells);
+        assert_eq!(1, diff.ptr_delta);
+        assert_eq!(inter.len() as isize, diff.ip_delta);
+
+        let diff = after.diff(&before);
+        assert_eq!(vec![(0, 1, 0), (1, 2, 0)], diff.cells);
+        assert_eq!(-1, diff.ptr_delta);
+        assert_eq!(StateDiff::default(), after.diff(&after));
+    }
+
     #[test]
     fn test_sample1() {
         let code = r#"
@@ -382,6 +5705,7 @@ We use a loop to compute 48 = 6 * 8
 
         assert_eq!(1, out.data.len());
         assert_eq!(55, out.data[0]);
+        assert_same_behavior(code, b"");
     }
 
     #[test]
@@ -438,8 +5762,9 @@ Pointer :   ^
         assert_eq!(13, out.data.len());
         assert_eq!(
             "Hello World!\n".as_bytes(),
-            out.data.iter().map(|c| *c).collect::<Vec<u8>>()
+            out.data.iter().copied().collect::<Vec<u8>>()
         );
+        assert_same_behavior(code, b"");
     }
 
     #[test]
@@ -1,3 +1,73 @@
 extern crate core;
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::interpreter::{BuildOptions, Interpreter, OptLevel};
+
+pub mod asm;
+pub mod bytecode;
+pub mod config;
+pub mod error;
+pub mod input;
 pub mod interpreter;
+mod optimizer;
+
+#[cfg(test)]
+mod reference;
+
+pub use error::BfError;
+
+/// Builds the program at `program` and runs it with the contents of `input`,
+/// or no input, writing its output to `output`.
+pub fn run_files(program: &Path, input: Option<&Path>, output: &Path) -> Result<(), BfError> {
+    let options = BuildOptions {
+        opt: OptLevel::O2,
+        ..Default::default()
+    };
+    let interpreter = Interpreter::build_bytes_with(&std::fs::read(program)?, &options)?;
+    let mut out = BufWriter::new(File::create(output)?);
+    match input {
+        Some(path) => interpreter.execute(&mut BufReader::new(File::open(path)?), &mut out)?,
+        None => interpreter.execute(&mut std::io::empty(), &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("brainfuck-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_run_files() {
+        let program = temp_file("run.bf", b",[+.,]");
+        let input = temp_file("run.in", b"HAL\0");
+        let output = temp_file("run.out", b"");
+
+        run_files(&program, Some(&input), &output).unwrap();
+        assert_eq!(b"IBM".to_vec(), std::fs::read(&output).unwrap());
+
+        std::fs::write(&program, "++++++++[>++++++++<-]>+.").unwrap();
+        run_files(&program, None, &output).unwrap();
+        assert_eq!(b"A".to_vec(), std::fs::read(&output).unwrap());
+
+        std::fs::write(&program, "[").unwrap();
+        let built = run_files(&program, None, &output);
+        let missing = program.with_extension("missing");
+        let missing = run_files(&missing, None, &output);
+        for path in [program, input, output] {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(matches!(built, Err(BfError::Build(_))));
+        assert!(matches!(missing, Err(BfError::Io(_))));
+    }
+}
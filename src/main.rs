@@ -1,18 +1,393 @@
-use std::io::{stdin, stdout};
+use std::fs::File;
+use std::io::{stderr, stdin, stdout, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Instant;
 
 use clap::Parser;
 
-use brainfuck::interpreter::Interpreter;
+use brainfuck::config::Config;
+use brainfuck::input::LineInput;
+use brainfuck::interpreter::{BuildOptions, Interpreter, OptLevel, VmState};
+use brainfuck::BfError;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    path: std::path::PathBuf,
+    /// Source files, built as one program in the given order
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Print the execution time and step count to stderr
+    #[arg(long, visible_alias = "stats")]
+    time: bool,
+    /// Compare the output with the contents of this file instead of printing it
+    #[arg(long, value_name = "FILE")]
+    expect: Option<PathBuf>,
+    /// Initial value of cell 0
+    #[arg(long, value_name = "N")]
+    cell0: Option<u8>,
+    /// Write every executed op with the pointer and current cell to this file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Read the program's input from this file instead of stdin
+    #[arg(long, value_name = "FILE")]
+    input_file: Option<PathBuf>,
+    /// Write the tape up to the last nonzero cell or the pointer to this
+    /// file when the program finishes
+    #[arg(long, value_name = "FILE")]
+    dump_tape: Option<PathBuf>,
+    /// Print the compiled ops with their addresses instead of running them
+    #[arg(long)]
+    dump: bool,
+    /// Only build the program, failing on unmatched brackets, without
+    /// running it
+    #[arg(long, conflicts_with = "dump")]
+    check: bool,
+}
+
+fn describe(byte: Option<&u8>) -> String {
+    match byte {
+        Some(&byte) => format!("{:?}", byte as char),
+        None => "end of output".to_string(),
+    }
+}
+
+/// Runs the program described by `args`, returning `false` when its output
+/// does not match the `--expect` file.
+fn run(
+    args: &Cli,
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    log: &mut dyn Write,
+) -> Result<bool, BfError> {
+    let mut code = vec![];
+    // The line each file starts at, for reporting build errors.
+    let mut starts = vec![];
+    for path in &args.paths {
+        if !starts.is_empty() {
+            code.push(b'\n');
+        }
+        starts.push(1 + code.iter().filter(|&&c| c == b'\n').count());
+        code.extend(std::fs::read(path)?);
+    }
+    let options = BuildOptions {
+        opt: OptLevel::O2,
+        ..Default::default()
+    };
+    let interpreter = match Interpreter::build_bytes_with(&code, &options) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            if args.paths.len() > 1 {
+                let file = starts
+                    .iter()
+                    .rposition(|&start| start <= err.line())
+                    .unwrap();
+                writeln!(
+                    log,
+                    "in {}, line {}",
+                    args.paths[file].display(),
+                    err.line() - starts[file] + 1
+                )?;
+            }
+            return Err(err.into());
+        }
+    };
+    if args.check {
+        return Ok(true);
+    }
+    if args.dump {
+        write!(output, "{}", interpreter.disassemble())?;
+        return Ok(true);
+    }
+
+    let mut file;
+    let input: &mut dyn Read = match &args.input_file {
+        Some(path) => {
+            file = BufReader::new(File::open(path)?);
+            &mut file
+        }
+        None => input,
+    };
+    let mut captured = vec![];
+    let out: &mut dyn Write = if args.expect.is_some() {
+        &mut captured
+    } else {
+        output
+    };
+    let config = Config {
+        initial_cells: args.cell0.into_iter().collect(),
+        ..Default::default()
+    };
+    let mut state = VmState::with_config(&config);
+    let start = Instant::now();
+    let stats = match &args.trace {
+        Some(path) => {
+            let mut trace = BufWriter::new(File::create(path)?);
+            let stats = interpreter.execute_traced(&mut state, input, out, &config, &mut trace)?;
+            trace.flush()?;
+            stats
+        }
+        None => interpreter.execute_with_state(&mut state, input, out, &config)?,
+    };
+    if let Some(path) = &args.dump_tape {
+        let tape = state.data();
+        let end = tape.iter().rposition(|&cell| cell != 0).unwrap_or(0);
+        std::fs::write(path, &tape[..end.max(state.ptr()) + 1])?;
+    }
+    if args.time {
+        writeln!(log, "time: {:?}, steps: {}", start.elapsed(), stats.steps)?;
+    }
+
+    if let Some(path) = &args.expect {
+        let expected = std::fs::read(path)?;
+        if let Some(idx) = (0..expected.len().max(captured.len()))
+            .find(|&idx| expected.get(idx) != captured.get(idx))
+        {
+            writeln!(
+                log,
+                "output mismatch at byte {}: expected {}, got {}",
+                idx,
+                describe(expected.get(idx)),
+                describe(captured.get(idx))
+            )?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
 fn main() {
     let args = Cli::parse();
-    let code = std::fs::read_to_string(&args.path).expect("could not read file");
-    let interpreter = Interpreter::build(&code).unwrap();
-    interpreter.execute(&mut stdin(), &mut stdout()).unwrap();
+    let mut input = LineInput::new(stdin().lock());
+    match run(&args, &mut input, &mut stdout(), &mut stderr()) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temp dir, removed when dropped so that a failing test
+    /// leaves nothing behind either.
+    struct TempFile(PathBuf);
+
+    impl std::ops::Deref for TempFile {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn temp_path(name: &str) -> TempFile {
+        TempFile(std::env::temp_dir().join(format!("brainfuck-{}-{}", std::process::id(), name)))
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> TempFile {
+        let file = temp_path(name);
+        std::fs::write(&*file, contents).unwrap();
+        file
+    }
+
+    fn run_cli(args: &[&str]) -> (Result<bool, BfError>, Vec<u8>, String) {
+        let cli = Cli::try_parse_from([&["brainfuck"], args].concat()).unwrap();
+        let mut output = vec![];
+        let mut log = vec![];
+        let result = run(&cli, &mut std::io::empty(), &mut output, &mut log);
+        (result, output, String::from_utf8(log).unwrap())
+    }
+
+    #[test]
+    fn test_cell0() {
+        let program = temp_file("cell0.bf", b".+.");
+        let program = program.to_str().unwrap();
+
+        let (result, output, _) = run_cli(&["--cell0", "65", program]);
+        assert!(result.unwrap());
+        assert_eq!(b"AB".to_vec(), output);
+
+        let (_, output, _) = run_cli(&[program]);
+        assert_eq!(vec![0, 1], output);
+
+        assert!(Cli::try_parse_from(["brainfuck", "--cell0", "256", program]).is_err());
+        assert!(Cli::try_parse_from(["brainfuck", "--cell0", "-1", program]).is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let program = temp_file("stats.bf", b"++[-].");
+        for flag in ["--time", "--stats"] {
+            let (result, output, log) = run_cli(&[flag, program.to_str().unwrap()]);
+            assert!(result.unwrap());
+            assert_eq!(vec![0], output);
+            let (time, steps) = log.split_once(", ").unwrap();
+            assert!(time.starts_with("time: "));
+            assert_eq!("steps: 3\n", steps);
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let program = temp_file("trace.bf", b"+.+.");
+        let trace = temp_path("trace.txt");
+
+        let (result, output, _) = run_cli(&[
+            "--trace",
+            trace.to_str().unwrap(),
+            program.to_str().unwrap(),
+        ]);
+        assert!(result.unwrap());
+        assert_eq!(vec![1, 2], output);
+        let trace = std::fs::read_to_string(&*trace).unwrap();
+        assert_eq!(4, trace.lines().count());
+        assert!(trace.starts_with("ip = 0, op = Add { d: 1 }, ptr = 0, cell = 0\n"));
+    }
+
+    #[test]
+    fn test_dump() {
+        let program = temp_file("dump.bf", b",[->+<]>.");
+        let (result, output, _) = run_cli(&["--dump", program.to_str().unwrap()]);
+        assert!(result.unwrap());
+        assert_eq!(
+            "    0  in\n    1  move_add 1\n    2  move 1\n    3  out\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let program = temp_file("check.bf", b",[.,]");
+        let (result, output, log) = run_cli(&["--check", program.to_str().unwrap()]);
+        assert!(result.unwrap());
+        assert!(output.is_empty());
+        assert!(log.is_empty());
+
+        let program = temp_file("check-bad.bf", b",[.,\n]]");
+        let (result, output, _) = run_cli(&["--check", program.to_str().unwrap()]);
+        assert_eq!(
+            "build err: bracket not match, line = 2, col = 2",
+            result.unwrap_err().to_string()
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_input_file() {
+        let program = temp_file("input-file.bf", b",[.,]");
+        let input = temp_file("input-file.txt", b"fixture");
+        let (result, output, _) = run_cli(&[
+            "--input-file",
+            input.to_str().unwrap(),
+            program.to_str().unwrap(),
+        ]);
+        assert!(result.is_err());
+        assert_eq!(b"fixture".to_vec(), output);
+
+        let program = temp_file("input-file-add.bf", b",>,[-<+>]<.");
+        let input = temp_file("input-file-add.txt", &[40, 25]);
+        let (result, output, _) = run_cli(&[
+            "--input-file",
+            input.to_str().unwrap(),
+            program.to_str().unwrap(),
+        ]);
+        assert!(result.unwrap());
+        assert_eq!(vec![65], output);
+
+        let missing = std::env::temp_dir().join("brainfuck-missing-input.txt");
+        let (result, _, _) = run_cli(&[
+            "--input-file",
+            missing.to_str().unwrap(),
+            program.to_str().unwrap(),
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_multiple_files() {
+        let first = temp_file("first.bf", b"++++++++[>++++++++\n");
+        let second = temp_file("second.bf", b"<-]>+.");
+        let (result, output, _) = run_cli(&[first.to_str().unwrap(), second.to_str().unwrap()]);
+        assert!(result.unwrap());
+        assert_eq!(b"A".to_vec(), output);
+
+        let third = temp_file("third.bf", b"+\n\n]");
+        let (result, _, log) = run_cli(&[
+            first.to_str().unwrap(),
+            second.to_str().unwrap(),
+            third.to_str().unwrap(),
+        ]);
+        assert_eq!(
+            "build err: bracket not match, line = 6, col = 1",
+            result.unwrap_err().to_string()
+        );
+        assert_eq!(format!("in {}, line 3\n", third.display()), log);
+
+        assert!(Cli::try_parse_from(["brainfuck"]).is_err());
+    }
+
+    #[test]
+    fn test_dump_tape() {
+        let program = temp_file("dump-tape.bf", b"++++++++[>++++++++<-]>+>>++>><");
+        let dump = temp_path("tape.bin");
+
+        let (result, _, _) = run_cli(&[
+            "--dump-tape",
+            dump.to_str().unwrap(),
+            program.to_str().unwrap(),
+        ]);
+        assert!(result.unwrap());
+        assert_eq!(vec![0, 65, 0, 2, 0], std::fs::read(&*dump).unwrap());
+
+        let program = temp_file("dump-tape-empty.bf", b"+-");
+        run_cli(&[
+            "--dump-tape",
+            dump.to_str().unwrap(),
+            program.to_str().unwrap(),
+        ])
+        .0
+        .unwrap();
+        assert_eq!(vec![0], std::fs::read(&*dump).unwrap());
+    }
+
+    #[test]
+    fn test_expect() {
+        let program = temp_file("expect.bf", b"++++++++[>++++++++<-]>+.+.");
+        let program = program.to_str().unwrap();
+        let good = temp_file("expect-good.txt", b"AB");
+        let bad = temp_file("expect-bad.txt", b"AC");
+        let short = temp_file("expect-short.txt", b"A");
+
+        let (result, output, log) = run_cli(&[program]);
+        assert!(result.unwrap());
+        assert_eq!(b"AB".to_vec(), output);
+        assert!(log.is_empty());
+
+        let (result, output, log) = run_cli(&["--expect", good.to_str().unwrap(), program]);
+        assert!(result.unwrap());
+        assert!(output.is_empty());
+        assert!(log.is_empty());
+
+        let (result, _, log) = run_cli(&["--expect", bad.to_str().unwrap(), program]);
+        assert!(!result.unwrap());
+        assert_eq!("output mismatch at byte 1: expected 'C', got 'B'\n", log);
+
+        let (result, _, log) = run_cli(&["--expect", short.to_str().unwrap(), program]);
+        assert!(!result.unwrap());
+        assert_eq!(
+            "output mismatch at byte 1: expected end of output, got 'B'\n",
+            log
+        );
+    }
 }
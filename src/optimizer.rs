@@ -0,0 +1,427 @@
+use crate::interpreter::Op::{
+    self, Add, AddAt, Clear, ClearRange, Dump, Halt, In, JmpNz, JmpZ, Move, MoveAdd, MulAdd, Out,
+    OutRange, PrintPtr, Scan, SwapAcc,
+};
+use crate::interpreter::{cleared_cells, BuildStats, OptLevel};
+use std::collections::HashMap;
+
+/// Runs the passes of `level`, counting the ops left after each in `stats`.
+/// With `wrap`, offsets from the pointer may name the same cell, so loops
+/// adding to or printing other cells are kept and loop counts are not worked
+/// out from the cells they were set from.
+pub(crate) fn optimize(
+    ops: Vec<Op>,
+    level: OptLevel,
+    wrap: bool,
+    stats: &mut BuildStats,
+) -> Vec<Op> {
+    let mut ops = match level {
+        OptLevel::O0 => ops,
+        _ => fold(ops),
+    };
+    stats.folded = ops.len();
+    if level == OptLevel::O2 {
+        ops = simple_loops(ops, wrap);
+        if !wrap {
+            ops = out_loops(counted_loops(ops));
+        }
+        stats.loops_removed = stats.folded - ops.len();
+        let len = ops.len();
+        ops = sink_moves(ops);
+        stats.moves_removed = len - ops.len();
+    }
+    link(&mut ops);
+    ops
+}
+
+/// Recomputes the jump addresses after ops have been added or removed.
+pub(crate) fn link(ops: &mut [Op]) {
+    let mut jmp_stack = vec![];
+    for i in 0..ops.len() {
+        match ops[i] {
+            JmpZ { .. } => jmp_stack.push(i),
+            JmpNz { .. } => {
+                let start = jmp_stack.pop().expect("unbalanced jumps");
+                ops[start] = JmpZ { addr: i + 1 };
+                ops[i] = JmpNz { addr: start + 1 };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Folds runs of moves and adds split by comments.
+pub(crate) fn fold(ops: Vec<Op>) -> Vec<Op> {
+    let mut result = Vec::with_capacity(ops.len());
+    for op in ops {
+        push_folded(&mut result, op);
+    }
+    result
+}
+
+/// Merges with the previous op when both are moves or both are adds, which
+/// happens when comments split a run of commands.
+pub(crate) fn push_folded(result: &mut Vec<Op>, op: Op) {
+    match (result.last_mut(), op) {
+        (Some(Move { d: prev }), Move { d }) | (Some(Add { d: prev }), Add { d }) => {
+            *prev += d;
+            if *prev == 0 {
+                result.pop();
+            }
+        }
+        _ => result.push(op),
+    }
+}
+
+/// `[-]` becomes `Clear`, and clears of neighbouring cells such as
+/// `[-]>[-]>[-]` are merged into a single `ClearRange`.
+fn push_clear(result: &mut Vec<Op>) {
+    let n = result.len();
+    if n >= 2 {
+        if let Move { d } = result[n - 1] {
+            let range = match result[n - 2] {
+                Clear => Some((0, 1)),
+                ClearRange { start_off, len } => Some((start_off, len)),
+                _ => None,
+            };
+            if let Some((start_off, len)) = range {
+                if d == start_off + len as isize || d == start_off - 1 {
+                    result[n - 2] = ClearRange {
+                        start_off: start_off.min(d),
+                        len: len + 1,
+                    };
+                    return;
+                }
+            }
+        }
+    }
+    result.push(Clear);
+}
+
+/// For a loop body of moves and adds that returns to its own cell, returns
+/// the offset and total of every add, including the one to the loop's cell.
+pub(crate) fn loop_effects(body: &[Op]) -> Option<Vec<(isize, isize)>> {
+    let mut off = 0;
+    let mut adds: Vec<(isize, isize)> = vec![];
+    for op in body {
+        match *op {
+            Move { d } => off += d,
+            Add { d } => match adds.iter_mut().find(|(o, _)| *o == off) {
+                Some((_, total)) => *total += d,
+                None => adds.push((off, d)),
+            },
+            _ => return None,
+        }
+    }
+    (off == 0).then_some(adds)
+}
+
+/// For a loop body like `->++>-<<`, which takes one from its own cell and
+/// adds constants around it, returns the offset and factor of every add.
+fn mul_loop(body: &[Op]) -> Option<Vec<(isize, isize)>> {
+    let mut adds = loop_effects(body)?;
+    let pos = adds.iter().position(|&(o, _)| o == 0)?;
+    if adds.remove(pos).1 != -1 {
+        return None;
+    }
+    adds.retain(|&(_, d)| d % 256 != 0);
+    Some(adds)
+}
+
+/// Replaces the simplest loops, `[-]`, `[>>]` and `[->+<]`, with single ops,
+/// and loops multiplying into their neighbours with `MulAdd`s and a clear.
+fn simple_loops(ops: Vec<Op>, wrap: bool) -> Vec<Op> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        match ops[i..] {
+            [JmpZ { .. }, Add { d }, JmpNz { .. }, ..] if d % 2 != 0 => {
+                push_clear(&mut result);
+                i += 3;
+            }
+            [JmpZ { .. }, Move { d }, JmpNz { .. }, ..] => {
+                result.push(Scan { d });
+                i += 3;
+            }
+            [JmpZ { .. }, ..] if !wrap => {
+                let end = ops[i + 1..]
+                    .iter()
+                    .position(|op| !matches!(op, Move { .. } | Add { .. }))
+                    .map(|n| i + 1 + n)
+                    .filter(|&end| matches!(ops[end], JmpNz { .. }));
+                match end.and_then(|end| Some((end, mul_loop(&ops[i + 1..end])?))) {
+                    Some((end, adds)) => {
+                        match adds[..] {
+                            [] => push_clear(&mut result),
+                            [(off, 1)] => result.push(MoveAdd { off }),
+                            _ => {
+                                for (off, factor) in adds {
+                                    result.push(MulAdd { off, factor });
+                                }
+                                result.push(Clear);
+                            }
+                        }
+                        i = end + 1;
+                    }
+                    None => {
+                        result.push(ops[i]);
+                        i += 1;
+                    }
+                }
+            }
+            [op @ (Move { .. } | Add { .. }), ..] => {
+                push_folded(&mut result, op);
+                i += 1;
+            }
+            _ => {
+                result.push(ops[i]);
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Within a straight run of `Move` and `Add`, addresses the adds relative to
+/// where the run starts, so its moves collapse into one at the end. The adds
+/// keep their order, which keeps overflow errors at the same op.
+fn sink_moves(ops: Vec<Op>) -> Vec<Op> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        let start = i;
+        while i < ops.len() && matches!(ops[i], Move { .. } | Add { .. }) {
+            i += 1;
+        }
+        if start == i {
+            result.push(ops[i]);
+            i += 1;
+            continue;
+        }
+
+        let run = &ops[start..i];
+        let mut sunk = vec![];
+        let mut off = 0;
+        for op in run {
+            match *op {
+                Move { d } => off += d,
+                Add { d } if off == 0 => sunk.push(Add { d }),
+                Add { d } => sunk.push(AddAt { off, d }),
+                _ => unreachable!(),
+            }
+        }
+        if off != 0 {
+            sunk.push(Move { d: off });
+        }
+        if sunk.len() < run.len() {
+            result.extend(sunk);
+        } else {
+            result.extend_from_slice(run);
+        }
+    }
+    result
+}
+
+/// Index of the `JmpNz` closing the loop that starts at `start`.
+fn loop_end(ops: &[Op], start: usize) -> usize {
+    let mut depth = 0;
+    for (i, op) in ops.iter().enumerate().skip(start) {
+        match op {
+            JmpZ { .. } => depth += 1,
+            JmpNz { .. } => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    unreachable!("unbalanced jumps")
+}
+
+/// How far `body` moves the pointer, or `None` if that depends on the tape
+/// because a loop or scan inside it does not return to where it started.
+pub(crate) fn net_move(body: &[Op]) -> Option<isize> {
+    let mut net = 0;
+    let mut i = 0;
+    while i < body.len() {
+        match body[i] {
+            Move { d } => net += d,
+            Scan { .. } => return None,
+            JmpZ { .. } => {
+                let end = loop_end(body, i);
+                if net_move(&body[i + 1..end])? != 0 {
+                    return None;
+                }
+                i = end;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    Some(net)
+}
+
+/// Runs loops whose iteration count is known before they start. Cells are
+/// known from the point the program clears or leaves a loop on them, since
+/// the initial tape can be set at runtime. A loop on a cell known to be 0 is
+/// dropped, and a loop of moves and adds that takes a fixed amount from its
+/// cell, like `[-->+++<]` on 6, becomes the adds of all its iterations.
+fn counted_loops(ops: Vec<Op>) -> Vec<Op> {
+    let mut result = Vec::with_capacity(ops.len());
+    // Known cell values, keyed by offset from `ptr`'s origin.
+    let mut known: HashMap<isize, isize> = HashMap::new();
+    let mut ptr = 0;
+    let mut i = 0;
+    while i < ops.len() {
+        let op = ops[i];
+        match op {
+            Move { d } => ptr += d,
+            Add { d } => add_known(&mut known, ptr, d),
+            AddAt { off, d } => add_known(&mut known, ptr + off, d),
+            Clear => {
+                known.insert(ptr, 0);
+            }
+            ClearRange { start_off, len } => {
+                for idx in cleared_cells(ptr, start_off, len) {
+                    known.insert(idx, 0);
+                }
+            }
+            MoveAdd { off } => {
+                let value = known.get(&ptr).copied();
+                match value {
+                    Some(value) => add_known(&mut known, ptr + off, value),
+                    None => {
+                        known.remove(&(ptr + off));
+                    }
+                }
+                known.insert(ptr, 0);
+            }
+            MulAdd { off, factor } => match known.get(&ptr).copied() {
+                Some(value) => add_known(&mut known, ptr + off, value * factor),
+                None => {
+                    known.remove(&(ptr + off));
+                }
+            },
+            OutRange { off, .. } => {
+                known.remove(&(ptr + off));
+                known.insert(ptr, 0);
+            }
+            In | SwapAcc => {
+                known.remove(&ptr);
+            }
+            Scan { .. } | JmpNz { .. } => {
+                known.clear();
+                known.insert(0, 0);
+                ptr = 0;
+            }
+            JmpZ { .. } => {
+                let end = loop_end(&ops, i);
+                match known.get(&ptr).copied() {
+                    Some(0) => {
+                        i = end + 1;
+                        continue;
+                    }
+                    Some(value) => {
+                        let effects = loop_effects(&ops[i + 1..end]);
+                        let step = effects
+                            .as_ref()
+                            .and_then(|adds| adds.iter().find(|&&(off, _)| off == 0))
+                            .map_or(0, |&(_, d)| d);
+                        if step < 0 && value % step == 0 {
+                            let count = value / -step;
+                            for (off, d) in effects.unwrap() {
+                                if off != 0 && d % 256 != 0 {
+                                    result.push(AddAt { off, d: d * count });
+                                    add_known(&mut known, ptr + off, d * count);
+                                }
+                            }
+                            result.push(Clear);
+                            known.insert(ptr, 0);
+                            i = end + 1;
+                            continue;
+                        }
+                    }
+                    None => {}
+                }
+                known.clear();
+                ptr = 0;
+            }
+            Out | Halt | Dump | PrintPtr => {}
+        }
+        result.push(op);
+        i += 1;
+    }
+    result
+}
+
+/// Replaces loops printing a sequence of bytes, like `[>.+<-]`, with an
+/// `OutRange`. The body may only take one from its own cell, in a single
+/// `-`, and add to the cell it prints, so the bytes follow from those two
+/// cells: the op counts its cell down to 0, each time printing the cell at
+/// `off` plus `pre` and then adding `step` to that cell.
+fn out_loops(ops: Vec<Op>) -> Vec<Op> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+    while i < ops.len() {
+        if let JmpZ { .. } = ops[i] {
+            let end = loop_end(&ops, i);
+            if let Some(op) = out_range(&ops[i + 1..end]) {
+                result.push(op);
+                i = end + 1;
+                continue;
+            }
+        }
+        result.push(ops[i]);
+        i += 1;
+    }
+    result
+}
+
+fn out_range(body: &[Op]) -> Option<Op> {
+    let mut off = 0;
+    let mut out = None;
+    for op in body {
+        match *op {
+            Move { d } => off += d,
+            Add { .. } => {}
+            Out if out.is_none() => out = Some(off),
+            _ => return None,
+        }
+    }
+    let out = out?;
+    if off != 0 || out == 0 {
+        return None;
+    }
+    let (mut off, mut printed, mut counted) = (0, false, false);
+    let (mut pre, mut step) = (0, 0);
+    for op in body {
+        match *op {
+            Move { d } => off += d,
+            Out => printed = true,
+            Add { d: -1 } if off == 0 && !counted => counted = true,
+            Add { d } if off == out => {
+                step += d;
+                if !printed {
+                    pre += d;
+                }
+            }
+            _ => return None,
+        }
+    }
+    if !counted {
+        return None;
+    }
+    Some(OutRange {
+        off: out,
+        pre: i8::try_from(pre).ok()?,
+        step: i8::try_from(step).ok()?,
+    })
+}
+
+fn add_known(known: &mut HashMap<isize, isize>, idx: isize, d: isize) {
+    if let Some(value) = known.get_mut(&idx) {
+        *value = (*value + d).rem_euclid(256);
+    }
+}
@@ -0,0 +1,149 @@
+//! A slow, unoptimized interpreter which walks the source one byte at a time.
+//! It defines the intended semantics, and the optimized interpreter is checked
+//! against it.
+
+use std::collections::VecDeque;
+
+use crate::interpreter::{Interpreter, OptLevel, VmState, TAPE_SIZE};
+
+pub(crate) struct Outcome {
+    pub output: Vec<u8>,
+    pub tape: Vec<u8>,
+    pub ok: bool,
+}
+
+fn matching(code: &[u8], from: usize) -> usize {
+    let mut depth = 0usize;
+    if code[from] == b'[' {
+        let mut i = from;
+        loop {
+            match code[i] {
+                b'[' => depth += 1,
+                b']' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return i;
+            }
+            i += 1;
+        }
+    } else {
+        let mut i = from;
+        loop {
+            match code[i] {
+                b']' => depth += 1,
+                b'[' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return i;
+            }
+            i -= 1;
+        }
+    }
+}
+
+pub(crate) fn run(code: &str, input: &[u8]) -> Outcome {
+    let mut input = VecDeque::from(input.to_vec());
+    let mut output = vec![];
+    let mut tape = vec![0u8; TAPE_SIZE];
+    let ok = walk(code.as_bytes(), &mut input, &mut output, &mut tape).is_some();
+    Outcome { output, tape, ok }
+}
+
+fn walk(
+    code: &[u8],
+    input: &mut VecDeque<u8>,
+    output: &mut Vec<u8>,
+    tape: &mut [u8],
+) -> Option<()> {
+    let mut ptr = 0usize;
+    let mut pc = 0usize;
+
+    while pc < code.len() {
+        match code[pc] {
+            b'>' if ptr + 1 < tape.len() => ptr += 1,
+            b'>' => return None,
+            b'<' => ptr = ptr.checked_sub(1)?,
+            b'+' => tape[ptr] = tape[ptr].wrapping_add(1),
+            b'-' => tape[ptr] = tape[ptr].wrapping_sub(1),
+            b'.' => output.push(tape[ptr]),
+            b',' => tape[ptr] = input.pop_front()?,
+            b'[' if tape[ptr] == 0 => pc = matching(code, pc),
+            b']' if tape[ptr] != 0 => pc = matching(code, pc),
+            _ => {}
+        }
+        pc += 1;
+    }
+
+    Some(())
+}
+
+pub(crate) fn assert_same_behavior(code: &str, input: &[u8]) {
+    let expected = run(code, input);
+
+    for opt in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        let interpreter = Interpreter::build_opt(code, opt).unwrap();
+        let mut state = VmState::new();
+        let mut output = vec![];
+        let result = interpreter.run(&mut state, &mut &input[..], &mut output, &mut vec![]);
+
+        assert_eq!(
+            expected.ok,
+            result.is_ok(),
+            "{:?} result differs: {:?}",
+            opt,
+            result
+        );
+        assert_eq!(expected.output, output, "{:?} output differs", opt);
+        if expected.ok {
+            assert!(expected.tape == state.data, "{:?} tape differs", opt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference() {
+        let out = run("++++++++[>++++++++<-]>+.,.", b"z");
+        assert!(out.ok);
+        assert_eq!(b"Az".to_vec(), out.output);
+        assert_eq!(0, out.tape[0]);
+        assert_eq!(b'z', out.tape[1]);
+
+        assert!(!run("<", b"").ok);
+        assert!(!run(",", b"").ok);
+    }
+
+    #[test]
+    fn test_same_behavior() {
+        assert_same_behavior("<+>-.,[]", b"\0");
+        assert_same_behavior("<><<>><+-++--+<>+-", b"");
+        assert_same_behavior(">>+++[-<+>]<<[-]>[->+>+<<]>>>+++++[<<->>-].", b"");
+        assert_same_behavior(",>,<[->+<]>.", b"!!");
+        assert_same_behavior(",[.,]", b"echo");
+        assert_same_behavior("+[>+]", b"");
+        assert_same_behavior("+++>++>+>+<<<[-]>[-]>[-]>[+]+<<[-]>>.", b"");
+        assert_same_behavior("+<", b"");
+        assert_same_behavior(">+[-]<[-]<[-]", b"");
+        assert_same_behavior("+++[>+>++>+++<<<-]>>>>+<<+<<.>.>.>.>.", b"");
+        assert_same_behavior(">+<<+", b"");
+        assert_same_behavior("+>+>+>+>+<<<<[>>]+++.[<<]", b"");
+        assert_same_behavior(">>+[<<<]", b"");
+        assert_same_behavior("+++>+++++[-<+>]<.>>++[<<+>>-]<<.[->>>+<<<]>>>.", b"");
+        assert_same_behavior("+[-<+>]", b"");
+        assert_same_behavior("++++++++>+++[-<->]<.>+++++[->>+++<-<-->]>.>.<<.", b"");
+        assert_same_behavior(">+++[-<->]", b"");
+        assert_same_behavior("[-]++++++[-->+++<]>.[.,]+>[-]+++[-<<+++>>]<.", b"");
+        assert_same_behavior(",[-]++[-<+>]", b"a");
+    }
+
+    #[test]
+    fn test_same_behavior_samples() {
+        assert_same_behavior(include_str!("../brainfuck/helloworld.bf"), b"");
+        assert_same_behavior(include_str!("../brainfuck/ascii_adder.bf"), b"!!");
+    }
+}